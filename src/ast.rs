@@ -5,6 +5,10 @@ use crate::typs::*;
 pub enum Decl {
     Type(String, Typ),
     Let(String, Term),
+    // exception constructor, along with the types of its arguments
+    Exception(String, Vec<Typ>),
+    // effect, along with its operations and their types
+    Effect(String, Vec<(String, Typ)>),
 }
 
 impl Decl {
@@ -15,4 +19,34 @@ impl Decl {
     pub fn let_(name: &str, term: Term) -> Self {
         Decl::Let(name.to_string(), term)
     }
+
+    pub fn exception(name: &str, args: Vec<Typ>) -> Self {
+        Decl::Exception(name.to_string(), args)
+    }
+
+    pub fn effect(name: &str, ops: Vec<(&str, Typ)>) -> Self {
+        Decl::Effect(
+            name.to_string(),
+            ops.into_iter().map(|(op, typ)| (op.to_string(), typ)).collect(),
+        )
+    }
+}
+
+// a declaration along with the doc comments (`/// ...`) preceding it
+#[derive(Debug)]
+pub struct Item {
+    pub doc: Option<String>,
+    pub decl: Decl,
+}
+
+impl Item {
+    pub fn new(doc: Vec<String>, decl: Decl) -> Self {
+        let doc = if doc.is_empty() {
+            None
+        } else {
+            Some(doc.join("\n"))
+        };
+
+        Item { doc, decl }
+    }
 }
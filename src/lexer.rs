@@ -0,0 +1,401 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+// tokens
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tok<'input> {
+    IdentUpper(&'input str),
+    IdentLower(&'input str),
+    Int(i32),
+    Doc(String),
+    Hole(&'input str),
+
+    // keywords
+    Break,
+    Catch,
+    Continue,
+    Do,
+    Effect,
+    Else,
+    Exception,
+    For,
+    Fun,
+    Handle,
+    If,
+    In,
+    Let,
+    Match,
+    Perform,
+    Raise,
+    Ref,
+    Return,
+    Then,
+    Try,
+    Type,
+    While,
+    With,
+
+    // symbols
+    Arrow,
+    Assign,
+    BackComp,
+    Bang,
+    Bar,
+    Colon,
+    ColonColon,
+    Comma,
+    DotDot,
+    Equals,
+    FatArrow,
+    LBrace,
+    LBracket,
+    LParen,
+    Pipe,
+    RBrace,
+    RBracket,
+    RParen,
+    Semi,
+    Underscore,
+    FwdComp,
+}
+
+const KEYWORDS: &[(&str, Tok<'static>)] = &[
+    ("break", Tok::Break),
+    ("catch", Tok::Catch),
+    ("continue", Tok::Continue),
+    ("do", Tok::Do),
+    ("effect", Tok::Effect),
+    ("else", Tok::Else),
+    ("exception", Tok::Exception),
+    ("for", Tok::For),
+    ("fun", Tok::Fun),
+    ("handle", Tok::Handle),
+    ("if", Tok::If),
+    ("in", Tok::In),
+    ("let", Tok::Let),
+    ("match", Tok::Match),
+    ("perform", Tok::Perform),
+    ("raise", Tok::Raise),
+    ("ref", Tok::Ref),
+    ("return", Tok::Return),
+    ("then", Tok::Then),
+    ("try", Tok::Try),
+    ("type", Tok::Type),
+    ("while", Tok::While),
+    ("with", Tok::With),
+];
+
+// longest symbols first, so that e.g. "=>" is never lexed as "=" ">"
+const SYMBOLS: &[(&str, Tok<'static>)] = &[
+    ("->", Tok::Arrow),
+    ("..", Tok::DotDot),
+    ("::", Tok::ColonColon),
+    (":=", Tok::Assign),
+    ("<<", Tok::BackComp),
+    ("=>", Tok::FatArrow),
+    (">>", Tok::FwdComp),
+    ("|>", Tok::Pipe),
+    ("!", Tok::Bang),
+    ("|", Tok::Bar),
+    (":", Tok::Colon),
+    (",", Tok::Comma),
+    ("=", Tok::Equals),
+    ("{", Tok::LBrace),
+    ("[", Tok::LBracket),
+    ("(", Tok::LParen),
+    ("}", Tok::RBrace),
+    ("]", Tok::RBracket),
+    (")", Tok::RParen),
+    (";", Tok::Semi),
+    ("_", Tok::Underscore),
+];
+
+impl std::fmt::Display for Tok<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tok::IdentUpper(s) | Tok::IdentLower(s) => write!(f, "{}", s),
+            Tok::Int(i) => write!(f, "{}", i),
+            Tok::Doc(_) => write!(f, "doc comment"),
+            Tok::Hole(s) => write!(f, "?{}", s),
+            tok => {
+                let text = KEYWORDS
+                    .iter()
+                    .chain(SYMBOLS)
+                    .find(|(_, t)| t == tok)
+                    .map(|(s, _)| *s)
+                    .unwrap();
+
+                write!(f, "{}", text)
+            }
+        }
+    }
+}
+
+// errors
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LexError {
+    UnexpectedChar(usize, char),
+    UnterminatedComment(usize),
+    InvalidInt(usize, String),
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LexError::UnexpectedChar(pos, c) => {
+                write!(f, "Unexpected character '{}' at {}", c, pos)
+            }
+            LexError::UnterminatedComment(pos) => {
+                write!(f, "Comment starting at {} is never closed", pos)
+            }
+            LexError::InvalidInt(pos, s) => write!(f, "Invalid integer \"{}\" at {}", s, pos),
+        }
+    }
+}
+
+pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
+
+// lexing
+
+pub struct Lexer<'input> {
+    input: &'input str,
+    chars: Peekable<CharIndices<'input>>,
+    // nesting of brackets, and whether a declaration may start here, i.e.
+    // at the top level after a ;
+    depth: usize,
+    decl_start: bool,
+}
+
+impl<'input> Lexer<'input> {
+    pub fn new(input: &'input str) -> Self {
+        Lexer {
+            input,
+            chars: input.char_indices().peekable(),
+            depth: 0,
+            decl_start: true,
+        }
+    }
+
+    fn rest(&mut self) -> &'input str {
+        match self.chars.peek() {
+            Some((i, _)) => &self.input[*i..],
+            None => "",
+        }
+    }
+
+    fn pos(&mut self) -> usize {
+        match self.chars.peek() {
+            Some((i, _)) => *i,
+            None => self.input.len(),
+        }
+    }
+
+    fn advance(&mut self, n: usize) {
+        for _ in 0..n {
+            self.chars.next();
+        }
+    }
+
+    fn take_while<P: Fn(char) -> bool>(&mut self, pred: P) -> &'input str {
+        let start = self.pos();
+        while let Some((_, c)) = self.chars.peek() {
+            if !pred(*c) {
+                break;
+            }
+            self.chars.next();
+        }
+
+        &self.input[start..self.pos()]
+    }
+
+    // skips whitespace and comments, returning the text of a doc comment
+    // if one is found
+    fn skip_trivia(&mut self) -> Result<Option<(usize, String, usize)>, LexError> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            let start = self.pos();
+            let rest = self.rest();
+            if rest.starts_with("///") && !rest.starts_with("////") {
+                self.advance(3);
+                let line = self.take_while(|c| c != '\n');
+                let line = line.strip_prefix(' ').unwrap_or(line);
+
+                return Ok(Some((start, line.trim_end().to_string(), self.pos())));
+            } else if rest.starts_with("//") {
+                self.take_while(|c| c != '\n');
+            } else if rest.starts_with("/*") {
+                self.skip_block_comment()?;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) -> Result<(), LexError> {
+        let start = self.pos();
+
+        let mut depth = 0;
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("/*") {
+                self.advance(2);
+                depth += 1;
+            } else if rest.starts_with("*/") {
+                self.advance(2);
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(());
+                }
+            } else if self.chars.next().is_none() {
+                return Err(LexError::UnterminatedComment(start));
+            }
+        }
+    }
+
+    fn lex_int(&mut self, start: usize) -> Spanned<Tok<'input>, usize, LexError> {
+        let rest = self.rest();
+        let prefix = ["0b", "0o", "0x"]
+            .into_iter()
+            .find(|p| rest.starts_with(p));
+
+        if let Some(p) = prefix {
+            self.advance(p.len());
+        }
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        let end = self.pos();
+        let text = &self.input[start..end];
+        match parse_int::parse::<i32>(text) {
+            Ok(i) => Ok((start, Tok::Int(i), end)),
+            Err(_) => Err(LexError::InvalidInt(start, text.to_string())),
+        }
+    }
+
+    fn lex_ident(&mut self, start: usize) -> Spanned<Tok<'input>, usize, LexError> {
+        let ident = self.take_while(|c| c.is_ascii_alphanumeric());
+        let end = self.pos();
+
+        if let Some((_, tok)) = KEYWORDS.iter().find(|(kw, _)| *kw == ident) {
+            return Ok((start, tok.clone(), end));
+        }
+
+        let tok = if ident.starts_with(|c: char| c.is_ascii_uppercase()) {
+            Tok::IdentUpper(ident)
+        } else {
+            Tok::IdentLower(ident)
+        };
+
+        Ok((start, tok, end))
+    }
+
+    fn lex_token(&mut self) -> Option<Spanned<Tok<'input>, usize, LexError>> {
+        let start = self.pos();
+        let c = self.chars.peek()?.1;
+
+        if c.is_ascii_digit() {
+            return Some(self.lex_int(start));
+        }
+
+        if c.is_ascii_alphabetic() {
+            return Some(self.lex_ident(start));
+        }
+
+        if c == '?' {
+            self.chars.next();
+            let name = self.take_while(|c| c.is_ascii_alphanumeric());
+            return Some(Ok((start, Tok::Hole(name), self.pos())));
+        }
+
+        let rest = self.rest();
+        if let Some((sym, tok)) = SYMBOLS.iter().find(|(sym, _)| rest.starts_with(sym)) {
+            self.advance(sym.chars().count());
+            return Some(Ok((start, tok.clone(), self.pos())));
+        }
+
+        Some(Err(LexError::UnexpectedChar(start, c)))
+    }
+}
+
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<Tok<'input>, usize, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut doc: Option<(usize, String, usize)> = None;
+        loop {
+            match self.skip_trivia() {
+                Err(why) => return Some(Err(why)),
+                Ok(None) => break,
+                Ok(Some((start, line, end))) => {
+                    doc = match doc {
+                        None => Some((start, line, end)),
+                        Some((start, text, _)) => Some((start, text + "\n" + &line, end)),
+                    };
+                }
+            }
+        }
+
+        // consecutive doc comment lines are merged into a single token, which
+        // is only emitted before a declaration and skipped anywhere else
+        if let Some((start, text, end)) = doc {
+            if self.decl_start && self.chars.peek().is_some() {
+                return Some(Ok((start, Tok::Doc(text), end)));
+            }
+        }
+
+        let tok = self.lex_token();
+        if let Some(Ok((_, tok, _))) = &tok {
+            match tok {
+                Tok::LParen | Tok::LBracket | Tok::LBrace => self.depth += 1,
+                Tok::RParen | Tok::RBracket | Tok::RBrace => {
+                    self.depth = self.depth.saturating_sub(1)
+                }
+                _ => {}
+            }
+            self.decl_start = matches!(tok, Tok::Semi) && self.depth == 0;
+        }
+
+        tok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(src: &str) -> Vec<String> {
+        Lexer::new(src)
+            .filter_map(|tok| match tok.unwrap().1 {
+                Tok::Doc(text) => Some(text),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn doc_before_decl() {
+        assert_eq!(docs("/// one\n/// two\nlet x = 1;"), vec!["one\ntwo"]);
+        assert_eq!(docs("let x = 1;\n/// y\nlet y = 2;"), vec!["y"]);
+    }
+
+    #[test]
+    fn doc_elsewhere_is_trivia() {
+        assert!(docs("let f = fun (x: Int) => {\n/// inside\nx};").is_empty());
+        assert!(docs("let x = (1;\n/// in parens\n2);").is_empty());
+        assert!(docs("let x = 1;\n/// at the end\n").is_empty());
+    }
+
+    // ? alone is an anonymous hole, reported without a name
+    #[test]
+    fn holes() {
+        let holes: Vec<_> = Lexer::new("?goal ? ?1")
+            .filter_map(|tok| match tok.unwrap().1 {
+                Tok::Hole(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(holes, vec!["goal", "", "1"]);
+    }
+}
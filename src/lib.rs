@@ -1,13 +1,24 @@
+use crate::ast::*;
+use crate::steps::*;
 use crate::terms::*;
 use crate::typs::*;
 use crate::vals::*;
 
 pub mod ast;
+pub mod lexer;
+pub mod norm;
+pub mod steps;
+pub mod subst;
 pub mod terms;
 pub mod typs;
 pub mod vals;
 
+#[macro_use]
+extern crate lalrpop_util;
+lalrpop_mod!(#[allow(clippy::all)] pub grammar);
+
 pub(crate) type Map<K, V> = immutable_map::TreeMap<K, V>;
+pub(crate) type Set<T> = immutable_map::TreeSet<T>;
 
 #[macro_export]
 macro_rules! cast {
@@ -28,6 +39,15 @@ pub enum ProgramError {
     ValError(vals::ValError),
 }
 
+impl std::fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProgramError::TypError(why) => write!(f, "Type error: {}", why),
+            ProgramError::ValError(why) => write!(f, "Eval error: {}", why),
+        }
+    }
+}
+
 pub type ProgramResult<T> = Result<T, ProgramError>;
 
 // ctx
@@ -36,6 +56,7 @@ pub type ProgramResult<T> = Result<T, ProgramError>;
 pub struct ProgramContext {
     typ_ctx: TypContext,
     val_ctx: ValContext,
+    evaluator: Evaluator,
 }
 
 impl ProgramContext {
@@ -46,21 +67,93 @@ impl ProgramContext {
         Some((val, typ))
     }
 
-    // pub fn insert_typ(&self, name: &str, typ: Typ) -> Self {
-    //     Context {
-    //         t_ctx: self.t_ctx.
-    //     }
-    // }
+    pub fn get_typ(&self, name: &str) -> Option<Typ> {
+        self.typ_ctx.get(name).cloned()
+    }
+
+    // binds a name for type checking only, e.g. a definition that failed to
+    // type check or couldn't be evaluated
+    pub fn insert_typ(&self, name: &str, typ: &Typ) -> Self {
+        ProgramContext {
+            typ_ctx: self.typ_ctx.insert(name.to_string(), typ.clone()),
+            val_ctx: self.val_ctx.clone(),
+            evaluator: self.evaluator,
+        }
+    }
 
     pub fn insert_term(&self, name: &str, term: &Term) -> ProgramResult<Self> {
         let (typ, val) = self.run(term)?;
         Ok(self.insert_val(name, &typ, &val))
     }
 
+    // declares an exception constructor taking arguments of the given types
+    pub fn insert_exception(&self, name: &str, args: &[Typ]) -> TypResult<Self> {
+        // the payload can't mention type variables, as nothing would tie
+        // those of a raise to those of a catch
+        if let Some(var) = args.iter().flat_map(Typ::vars).next() {
+            return Err(TypError::Unbound(var));
+        }
+
+        let typ = args
+            .iter()
+            .rev()
+            .fold(Typ::exn(), |typ, arg| Typ::func(arg.clone(), typ));
+
+        Ok(self.insert_val(name, &typ, &Val::constructor(name, args.len())))
+    }
+
+    // declares the operations of an effect, given their types as functions
+    pub fn insert_effect(&self, name: &str, ops: &[(String, Typ)]) -> TypResult<Self> {
+        let mut ctx = ProgramContext {
+            typ_ctx: self.typ_ctx.clone(),
+            val_ctx: self.val_ctx.clone(),
+            evaluator: self.evaluator,
+        };
+        for (op, typ) in ops {
+            let (from, to) = match typ {
+                Typ::Func { from, to, .. } => (*from.clone(), *to.clone()),
+                _ => {
+                    return Err(TypError::NotAnArrow {
+                        effect: name.to_string(),
+                        op: op.clone(),
+                        typ: typ.clone(),
+                    })
+                }
+            };
+            // as with exceptions, nothing would tie the type variables of a
+            // perform to those of its handler
+            if let Some(var) = typ.vars().into_iter().next() {
+                return Err(TypError::Unbound(var));
+            }
+
+            ctx = ctx.insert_val(op, &Typ::op(name, from, to), &Val::Op(op.clone()));
+        }
+
+        Ok(ctx)
+    }
+
     pub fn insert_val(&self, name: &str, typ: &Typ, val: &Val) -> Self {
         ProgramContext {
             typ_ctx: self.typ_ctx.insert(name.to_string(), typ.clone()),
             val_ctx: self.val_ctx.insert(name.to_string(), val.clone()),
+            evaluator: self.evaluator,
+        }
+    }
+
+    // callcc is only defined under the evaluator which can capture its
+    // continuation, so elsewhere using it is caught by the checker
+    pub fn with_evaluator(self, evaluator: Evaluator) -> Self {
+        let ctx = ProgramContext { evaluator, ..self };
+        match evaluator {
+            Evaluator::Cps => ctx.insert_val("callcc", &callcc(), &Val::CallCC),
+            _ => match (ctx.typ_ctx.remove("callcc"), ctx.val_ctx.remove("callcc")) {
+                (Some((typ_ctx, _)), Some((val_ctx, Val::CallCC))) => ProgramContext {
+                    typ_ctx,
+                    val_ctx,
+                    evaluator,
+                },
+                _ => ctx,
+            },
         }
     }
 
@@ -68,8 +161,34 @@ impl ProgramContext {
         term.typ(&self.typ_ctx)
     }
 
+    pub fn check(&self, term: &Term, typ: &Typ) -> TypResult<()> {
+        term.check(&self.typ_ctx, typ)
+    }
+
     pub fn eval(&self, term: &Term) -> ValResult<Val> {
-        term.eval(&self.val_ctx)
+        match self.evaluator {
+            Evaluator::Direct => term.eval(&self.val_ctx),
+            Evaluator::Cps => term.eval_cps(&self.val_ctx),
+            Evaluator::Lazy => term.eval_in(&self.val_ctx, Strategy::ByNeed),
+        }
+    }
+
+    // evaluates the term by small steps, passing each of them to on_step
+    pub fn trace<F: FnMut(&Step)>(
+        &self,
+        term: &Term,
+        order: Order,
+        mut on_step: F,
+    ) -> ValResult<Val> {
+        let mut val_ctx = self.val_ctx.clone();
+
+        let mut term = term.clone();
+        while let Some(step) = term.step(order, &mut val_ctx)? {
+            on_step(&step);
+            term = step.term();
+        }
+
+        term.eval(&val_ctx)
     }
 
     pub fn run(&self, term: &Term) -> ProgramResult<(Typ, Val)> {
@@ -85,6 +204,36 @@ impl ProgramContext {
 
         Ok((typ, val))
     }
+
+    // the value of the term in normal form
+    pub fn normalize(&self, term: &Term) -> ProgramResult<(Typ, Term)> {
+        let (typ, val) = self.run(term)?;
+        let term = self.reify(&val, &typ);
+
+        Ok((typ, term))
+    }
+
+    pub fn reify(&self, val: &Val, typ: &Typ) -> Term {
+        val.reify(typ, &self.val_ctx)
+    }
+
+    // the declaration as --doc shows it: its doc comment, then what it binds
+    // along with the types; None for declarations binding nothing
+    pub fn describe(&self, item: &Item) -> Option<String> {
+        let decl = match &item.decl {
+            Decl::Let(name, _) | Decl::Exception(name, _) => {
+                format!("{} : {}", name, self.get_typ(name)?)
+            }
+            Decl::Effect(name, ops) => {
+                let ops: Vec<_> = ops.iter().map(|(op, typ)| format!("{} : {}", op, typ)).collect();
+                format!("effect {} {{ {} }}", name, ops.join(", "))
+            }
+            Decl::Type(_, _) => return None,
+        };
+
+        let doc = item.doc.iter().flat_map(|doc| doc.lines());
+        Some(doc.map(|line| format!("/// {}\n", line)).collect::<String>() + &decl)
+    }
 }
 
 impl ProgramContext {
@@ -92,6 +241,7 @@ impl ProgramContext {
         ProgramContext {
             typ_ctx: TypContext::new(),
             val_ctx: ValContext::new(),
+            evaluator: Evaluator::Direct,
         }
     }
 }
@@ -102,6 +252,8 @@ impl Default for ProgramContext {
             // unit
             .insert_val("Unit", &Typ::atom("Unit"), &Val::Unit)
             // bool
+            .insert_val("True", &Typ::atom("Bool"), &Val::Bool(true))
+            .insert_val("False", &Typ::atom("Bool"), &Val::Bool(false))
             .insert_val(
                 "not",
                 &typs::func!(Typ::atom("Bool"), Typ::atom("Bool")),
@@ -310,5 +462,810 @@ impl Default for ProgramContext {
                     Ok(Val::Int(x >> y))
                 }),
             )
+            // pair
+            .insert_val(
+                "fst",
+                &Typ::forall(&["a", "b"], typs::func!(Typ::pair(a(), b()), a())),
+                &Val::op1(|p| {
+                    let p = cast!(p, Val::Pair);
+
+                    Ok(p.0.clone())
+                }),
+            )
+            .insert_val(
+                "snd",
+                &Typ::forall(&["a", "b"], typs::func!(Typ::pair(a(), b()), b())),
+                &Val::op1(|p| {
+                    let p = cast!(p, Val::Pair);
+
+                    Ok(p.1.clone())
+                }),
+            )
+            // list
+            .insert_val(
+                "map",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(effectful(a(), b()), effectful(Typ::list(a()), Typ::list(b()))),
+                ),
+                &Val::op2(|f, xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    Ok(Val::List(
+                        xs.iter().map(|x| f.apply(x.clone())).collect::<ValResult<_>>()?,
+                    ))
+                }),
+            )
+            .insert_val(
+                "filter",
+                &Typ::forall(
+                    &["a", CALLBACK_ROW],
+                    typs::func!(
+                        effectful(a(), Typ::atom("Bool")),
+                        effectful(Typ::list(a()), Typ::list(a()))
+                    ),
+                ),
+                &Val::op2(|f, xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    let mut kept = vec![];
+                    for x in xs.iter() {
+                        if cast!(f.apply(x.clone())?, Val::Bool) {
+                            kept.push(x.clone());
+                        }
+                    }
+
+                    Ok(Val::List(kept.into_iter().collect()))
+                }),
+            )
+            .insert_val(
+                "foldl",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        typs::func!(b(), effectful(a(), b())),
+                        b(),
+                        effectful(Typ::list(a()), b())
+                    ),
+                ),
+                &Val::op3(|f, acc, xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    xs.iter().try_fold(acc, |acc, x| f.apply(acc)?.apply(x.clone()))
+                }),
+            )
+            .insert_val(
+                "foldr",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        typs::func!(a(), effectful(b(), b())),
+                        b(),
+                        effectful(Typ::list(a()), b())
+                    ),
+                ),
+                &Val::op3(|f, acc, xs| {
+                    let xs = cast!(xs, Val::List);
+                    let xs: Vec<_> = xs.iter().collect();
+
+                    xs.into_iter()
+                        .rev()
+                        .try_fold(acc, |acc, x| f.apply(x.clone())?.apply(acc))
+                }),
+            )
+            .insert_val(
+                "length",
+                &Typ::forall(&["a"], typs::func!(Typ::list(a()), Typ::atom("Int"))),
+                &Val::op1(|xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    Ok(Val::Int(xs.iter().count() as i32))
+                }),
+            )
+            .insert_val(
+                "reverse",
+                &Typ::forall(&["a"], typs::func!(Typ::list(a()), Typ::list(a()))),
+                &Val::op1(|xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    Ok(Val::List(
+                        xs.iter()
+                            .fold(List::nil(), |tail, x| List::cons(x.clone(), tail)),
+                    ))
+                }),
+            )
+            .insert_val(
+                "append",
+                &Typ::forall(
+                    &["a"],
+                    typs::func!(Typ::list(a()), Typ::list(a()), Typ::list(a())),
+                ),
+                &Val::op2(|xs, ys| {
+                    let xs = cast!(xs, Val::List);
+                    let ys = cast!(ys, Val::List);
+
+                    let xs: Vec<_> = xs.iter().collect();
+                    Ok(Val::List(
+                        xs.into_iter()
+                            .rev()
+                            .fold(ys, |tail, x| List::cons(x.clone(), tail)),
+                    ))
+                }),
+            )
+            .insert_val(
+                "range",
+                &typs::func!(
+                    Typ::atom("Int"),
+                    Typ::atom("Int"),
+                    Typ::list(Typ::atom("Int"))
+                ),
+                &Val::op2(|from, to| {
+                    let from = cast!(from, Val::Int);
+                    let to = cast!(to, Val::Int);
+
+                    Ok(Val::List((from..to).map(Val::Int).collect()))
+                }),
+            )
+            .insert_val(
+                "zip",
+                &Typ::forall(
+                    &["a", "b"],
+                    typs::func!(
+                        Typ::list(a()),
+                        Typ::list(b()),
+                        Typ::list(Typ::pair(a(), b()))
+                    ),
+                ),
+                &Val::op2(|xs, ys| {
+                    let xs = cast!(xs, Val::List);
+                    let ys = cast!(ys, Val::List);
+
+                    Ok(Val::List(
+                        xs.iter()
+                            .zip(ys.iter())
+                            .map(|(x, y)| Val::pair(x.clone(), y.clone()))
+                            .collect(),
+                    ))
+                }),
+            )
+            .insert_val(
+                "sum",
+                &typs::func!(Typ::list(Typ::atom("Int")), Typ::atom("Int")),
+                &Val::op1(|xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    xs.iter()
+                        .try_fold(0i32, |sum, x| sum.checked_add(*cast!(x, Val::Int)))
+                        .map(Val::Int)
+                        .ok_or(ValError::Overflow)
+                }),
+            )
+            // option
+            .insert_val(
+                "Some",
+                &Typ::forall(&["a"], typs::func!(a(), option(a()))),
+                &Val::op1(|x| Ok(Val::con("Some", vec![x]))),
+            )
+            .insert_val(
+                "None",
+                &Typ::forall(&["a"], option(a())),
+                &Val::con("None", vec![]),
+            )
+            .insert_val(
+                "optionMap",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(effectful(a(), b()), effectful(option(a()), option(b()))),
+                ),
+                &Val::op2(|f, x| match x {
+                    Val::Con(name, args) if name == "Some" => {
+                        Ok(Val::con("Some", vec![f.apply(args[0].clone())?]))
+                    }
+                    x => Ok(x),
+                }),
+            )
+            .insert_val(
+                "optionAndThen",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        effectful(a(), option(b())),
+                        effectful(option(a()), option(b()))
+                    ),
+                ),
+                &Val::op2(|f, x| match x {
+                    Val::Con(name, args) if name == "Some" => f.apply(args[0].clone()),
+                    x => Ok(x),
+                }),
+            )
+            .insert_val(
+                "optionUnwrapOr",
+                &Typ::forall(&["a"], typs::func!(a(), option(a()), a())),
+                &Val::op2(|default, x| match x {
+                    Val::Con(name, args) if name == "Some" => Ok(args[0].clone()),
+                    _ => Ok(default),
+                }),
+            )
+            .insert_val(
+                "okOr",
+                &Typ::forall(&["e", "a"], typs::func!(e(), option(a()), result(e(), a()))),
+                &Val::op2(|err, x| match x {
+                    Val::Con(name, args) if name == "Some" => Ok(Val::con("Ok", args)),
+                    _ => Ok(Val::con("Err", vec![err])),
+                }),
+            )
+            // result
+            .insert_val(
+                "Ok",
+                &Typ::forall(&["e", "a"], typs::func!(a(), result(e(), a()))),
+                &Val::op1(|x| Ok(Val::con("Ok", vec![x]))),
+            )
+            .insert_val(
+                "Err",
+                &Typ::forall(&["e", "a"], typs::func!(e(), result(e(), a()))),
+                &Val::op1(|x| Ok(Val::con("Err", vec![x]))),
+            )
+            .insert_val(
+                "resultMap",
+                &Typ::forall(
+                    &["e", "a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        effectful(a(), b()),
+                        effectful(result(e(), a()), result(e(), b()))
+                    ),
+                ),
+                &Val::op2(|f, x| match x {
+                    Val::Con(name, args) if name == "Ok" => {
+                        Ok(Val::con("Ok", vec![f.apply(args[0].clone())?]))
+                    }
+                    x => Ok(x),
+                }),
+            )
+            .insert_val(
+                "resultAndThen",
+                &Typ::forall(
+                    &["e", "a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        effectful(a(), result(e(), b())),
+                        effectful(result(e(), a()), result(e(), b()))
+                    ),
+                ),
+                &Val::op2(|f, x| match x {
+                    Val::Con(name, args) if name == "Ok" => f.apply(args[0].clone()),
+                    x => Ok(x),
+                }),
+            )
+            .insert_val(
+                "resultUnwrapOr",
+                &Typ::forall(&["e", "a"], typs::func!(a(), result(e(), a()), a())),
+                &Val::op2(|default, x| match x {
+                    Val::Con(name, args) if name == "Ok" => Ok(args[0].clone()),
+                    _ => Ok(default),
+                }),
+            )
+            // safe variants of partial built-ins
+            .insert_val(
+                "checkedDiv",
+                &typs::func!(Typ::atom("Int"), Typ::atom("Int"), option(Typ::atom("Int"))),
+                &Val::op2(|x, y| {
+                    let x = cast!(x, Val::Int);
+                    let y = cast!(y, Val::Int);
+
+                    Ok(match x.checked_div(y) {
+                        Some(z) => Val::con("Some", vec![Val::Int(z)]),
+                        None => Val::con("None", vec![]),
+                    })
+                }),
+            )
+            .insert_val(
+                "checkedMod",
+                &typs::func!(Typ::atom("Int"), Typ::atom("Int"), option(Typ::atom("Int"))),
+                &Val::op2(|x, y| {
+                    let x = cast!(x, Val::Int);
+                    let y = cast!(y, Val::Int);
+
+                    Ok(match x.checked_rem(y) {
+                        Some(z) => Val::con("Some", vec![Val::Int(z)]),
+                        None => Val::con("None", vec![]),
+                    })
+                }),
+            )
+            // map
+            .insert_val(
+                "mapEmpty",
+                &Typ::forall(&["k", "v"], map(k(), v())),
+                &Val::Map(Map::new()),
+            )
+            .insert_val(
+                "mapInsert",
+                &Typ::forall(&["k", "v"], typs::func!(k(), v(), map(k(), v()), map(k(), v()))),
+                &Val::op3(|k, v, m| {
+                    let m = cast!(m, Val::Map);
+
+                    Ok(Val::Map(m.insert(Key::new(k)?, v)))
+                }),
+            )
+            .insert_val(
+                "mapLookup",
+                &Typ::forall(&["k", "v"], typs::func!(k(), map(k(), v()), option(v()))),
+                &Val::op2(|k, m| {
+                    let m = cast!(m, Val::Map);
+
+                    Ok(match m.get(&Key::new(k)?) {
+                        Some(v) => Val::con("Some", vec![v.clone()]),
+                        None => Val::con("None", vec![]),
+                    })
+                }),
+            )
+            .insert_val(
+                "mapRemove",
+                &Typ::forall(&["k", "v"], typs::func!(k(), map(k(), v()), map(k(), v()))),
+                &Val::op2(|k, m| {
+                    let m = cast!(m, Val::Map);
+
+                    Ok(match m.remove(&Key::new(k)?) {
+                        Some((removed, _)) => Val::Map(removed),
+                        None => Val::Map(m),
+                    })
+                }),
+            )
+            .insert_val(
+                "mapSize",
+                &Typ::forall(&["k", "v"], typs::func!(map(k(), v()), Typ::atom("Int"))),
+                &Val::op1(|m| {
+                    let m = cast!(m, Val::Map);
+
+                    Ok(Val::Int(m.len() as i32))
+                }),
+            )
+            .insert_val(
+                "mapToList",
+                &Typ::forall(
+                    &["k", "v"],
+                    typs::func!(map(k(), v()), Typ::list(Typ::pair(k(), v()))),
+                ),
+                &Val::op1(|m| {
+                    let m = cast!(m, Val::Map);
+
+                    Ok(Val::List(
+                        m.iter().map(|(k, v)| Val::pair(k.val().clone(), v.clone())).collect(),
+                    ))
+                }),
+            )
+            .insert_val(
+                "mapFromList",
+                &Typ::forall(
+                    &["k", "v"],
+                    typs::func!(Typ::list(Typ::pair(k(), v())), map(k(), v())),
+                ),
+                &Val::op1(|kvs| {
+                    let kvs = cast!(kvs, Val::List);
+
+                    let m = kvs.iter().try_fold(Map::new(), |m, kv| {
+                        let kv = cast!(kv, Val::Pair);
+
+                        Ok(m.insert(Key::new(kv.0.clone())?, kv.1.clone()))
+                    })?;
+
+                    Ok(Val::Map(m))
+                }),
+            )
+            // entries of the first map win over those of the second
+            .insert_val(
+                "mapUnion",
+                &Typ::forall(
+                    &["k", "v"],
+                    typs::func!(map(k(), v()), map(k(), v()), map(k(), v())),
+                ),
+                &Val::op2(|m1, m2| {
+                    let m1 = cast!(m1, Val::Map);
+                    let m2 = cast!(m2, Val::Map);
+
+                    Ok(Val::Map(m1.iter().fold(m2, |m, (k, v)| m.insert(k.clone(), v.clone()))))
+                }),
+            )
+            // folds over the entries in increasing order of keys
+            .insert_val(
+                "mapFold",
+                &Typ::forall(
+                    &["k", "v", "b", CALLBACK_ROW],
+                    typs::func!(
+                        typs::func!(k(), v(), effectful(b(), b())),
+                        b(),
+                        effectful(map(k(), v()), b())
+                    ),
+                ),
+                &Val::op3(|f, acc, m| {
+                    let m = cast!(m, Val::Map);
+
+                    m.iter().try_fold(acc, |acc, (k, v)| {
+                        f.apply(k.val().clone())?.apply(v.clone())?.apply(acc)
+                    })
+                }),
+            )
+            // set
+            .insert_val(
+                "setEmpty",
+                &Typ::forall(&["a"], set(a())),
+                &Val::Set(Set::new()),
+            )
+            .insert_val(
+                "setInsert",
+                &Typ::forall(&["a"], typs::func!(a(), set(a()), set(a()))),
+                &Val::op2(|x, s| {
+                    let s = cast!(s, Val::Set);
+
+                    Ok(Val::Set(s.insert(Key::new(x)?)))
+                }),
+            )
+            .insert_val(
+                "setMember",
+                &Typ::forall(&["a"], typs::func!(a(), set(a()), Typ::atom("Bool"))),
+                &Val::op2(|x, s| {
+                    let s = cast!(s, Val::Set);
+
+                    Ok(Val::Bool(s.contains(&Key::new(x)?)))
+                }),
+            )
+            .insert_val(
+                "setRemove",
+                &Typ::forall(&["a"], typs::func!(a(), set(a()), set(a()))),
+                &Val::op2(|x, s| {
+                    let s = cast!(s, Val::Set);
+
+                    Ok(match s.remove(&Key::new(x)?) {
+                        Some((removed, _)) => Val::Set(removed),
+                        None => Val::Set(s),
+                    })
+                }),
+            )
+            .insert_val(
+                "setSize",
+                &Typ::forall(&["a"], typs::func!(set(a()), Typ::atom("Int"))),
+                &Val::op1(|s| {
+                    let s = cast!(s, Val::Set);
+
+                    Ok(Val::Int(s.len() as i32))
+                }),
+            )
+            .insert_val(
+                "setToList",
+                &Typ::forall(&["a"], typs::func!(set(a()), Typ::list(a()))),
+                &Val::op1(|s| {
+                    let s = cast!(s, Val::Set);
+
+                    Ok(Val::List(s.iter().map(|x| x.val().clone()).collect()))
+                }),
+            )
+            .insert_val(
+                "setFromList",
+                &Typ::forall(&["a"], typs::func!(Typ::list(a()), set(a()))),
+                &Val::op1(|xs| {
+                    let xs = cast!(xs, Val::List);
+
+                    let s = xs
+                        .iter()
+                        .try_fold(Set::new(), |s, x| Ok(s.insert(Key::new(x.clone())?)))?;
+
+                    Ok(Val::Set(s))
+                }),
+            )
+            .insert_val(
+                "setUnion",
+                &Typ::forall(&["a"], typs::func!(set(a()), set(a()), set(a()))),
+                &Val::op2(|s1, s2| {
+                    let s1 = cast!(s1, Val::Set);
+                    let s2 = cast!(s2, Val::Set);
+
+                    Ok(Val::Set(s1.iter().fold(s2, |s, x| s.insert(x.clone()))))
+                }),
+            )
+            // folds over the elements in increasing order
+            .insert_val(
+                "setFold",
+                &Typ::forall(
+                    &["a", "b", CALLBACK_ROW],
+                    typs::func!(
+                        typs::func!(a(), effectful(b(), b())),
+                        b(),
+                        effectful(set(a()), b())
+                    ),
+                ),
+                &Val::op3(|f, acc, s| {
+                    let s = cast!(s, Val::Set);
+
+                    s.iter().try_fold(acc, |acc, x| f.apply(x.val().clone())?.apply(acc))
+                }),
+            )
+            // array
+            .insert_val(
+                "arrayMake",
+                &Typ::forall(&["a"], typs::func!(Typ::atom("Int"), a(), array(a()))),
+                &Val::op2(|n, x| {
+                    let n = cast!(n, Val::Int);
+                    if n < 0 {
+                        return Err(ValError::NegativeLength(n));
+                    }
+
+                    let mut arr = vec![];
+                    if n > MAX_ARRAY_LENGTH || arr.try_reserve_exact(n as usize).is_err() {
+                        return Err(ValError::TooLong(n));
+                    }
+                    arr.resize(n as usize, x);
+                    Ok(Val::array(arr))
+                }),
+            )
+            .insert_val(
+                "arrayGet",
+                &Typ::forall(&["a"], typs::func!(Typ::atom("Int"), array(a()), a())),
+                &Val::op2(|i, arr| {
+                    let i = cast!(i, Val::Int);
+                    let arr = cast!(arr, Val::Array);
+                    let arr = arr.borrow();
+
+                    Ok(arr[index(i, arr.len())?].clone())
+                }),
+            )
+            .insert_val(
+                "arraySet",
+                &Typ::forall(&["a"], typs::func!(Typ::atom("Int"), a(), array(a()), Typ::atom("Unit"))),
+                &Val::op3(|i, x, arr| {
+                    let i = cast!(i, Val::Int);
+                    let arr = cast!(arr, Val::Array);
+                    let mut arr = arr.borrow_mut();
+
+                    let i = index(i, arr.len())?;
+                    arr[i] = x;
+
+                    Ok(Val::Unit)
+                }),
+            )
+            .insert_val(
+                "arrayLength",
+                &Typ::forall(&["a"], typs::func!(array(a()), Typ::atom("Int"))),
+                &Val::op1(|arr| {
+                    let arr = cast!(arr, Val::Array);
+                    let len = arr.borrow().len();
+
+                    Ok(Val::Int(len as i32))
+                }),
+            )
+            .insert_val(
+                "arrayPush",
+                &Typ::forall(&["a"], typs::func!(a(), array(a()), Typ::atom("Unit"))),
+                &Val::op2(|x, arr| {
+                    let arr = cast!(arr, Val::Array);
+                    arr.borrow_mut().push(x);
+
+                    Ok(Val::Unit)
+                }),
+            )
+            // copies the elements from start (inclusive) to end (exclusive)
+            // into a new array
+            .insert_val(
+                "arraySlice",
+                &Typ::forall(
+                    &["a"],
+                    typs::func!(Typ::atom("Int"), Typ::atom("Int"), array(a()), array(a())),
+                ),
+                &Val::op3(|start, end, arr| {
+                    let start = cast!(start, Val::Int);
+                    let end = cast!(end, Val::Int);
+                    let arr = cast!(arr, Val::Array);
+                    let arr = arr.borrow();
+
+                    if start < 0 || end < start || end as usize > arr.len() {
+                        return Err(ValError::InvalidSlice {
+                            start,
+                            end,
+                            length: arr.len(),
+                        });
+                    }
+
+                    Ok(Val::array(arr[start as usize..end as usize].to_vec()))
+                }),
+            )
+            // stream
+            //
+            // the callbacks of streams are pure, as they run whenever the
+            // stream is forced rather than where it's built
+            .insert_val(
+                "streamEmpty",
+                &Typ::forall(&["a"], stream(a())),
+                &Val::Stream(Stream::nil()),
+            )
+            // the tail is only built once the stream is forced past the head
+            .insert_val(
+                "streamCons",
+                &Typ::forall(
+                    &["a"],
+                    typs::func!(a(), typs::func!(Typ::atom("Unit"), stream(a())), stream(a())),
+                ),
+                &Val::op2(|x, f| {
+                    let tail = Stream::delay(move || cast!(f.apply(Val::Unit)?, Val::Stream).force());
+                    Ok(Val::Stream(Stream::cons(x, tail)))
+                }),
+            )
+            .insert_val(
+                "streamUncons",
+                &Typ::forall(
+                    &["a"],
+                    typs::func!(stream(a()), option(Typ::pair(a(), stream(a())))),
+                ),
+                &Val::op1(|s| match cast!(s, Val::Stream).force()? {
+                    Some((x, rest)) => Ok(Val::con("Some", vec![Val::pair(x, Val::Stream(rest))])),
+                    None => Ok(Val::con("None", vec![])),
+                }),
+            )
+            // x, f x, f (f x), ...
+            .insert_val(
+                "iterate",
+                &Typ::forall(&["a"], typs::func!(typs::func!(a(), a()), a(), stream(a()))),
+                &Val::op2(|f, x| Ok(Val::Stream(iterate(f, x)))),
+            )
+            // the elements produced from the seed until f returns None
+            .insert_val(
+                "unfold",
+                &Typ::forall(
+                    &["a", "b"],
+                    typs::func!(
+                        typs::func!(b(), option(Typ::pair(a(), b()))),
+                        b(),
+                        stream(a())
+                    ),
+                ),
+                &Val::op2(|f, seed| Ok(Val::Stream(unfold(f, seed)))),
+            )
+            .insert_val(
+                "streamMap",
+                &Typ::forall(
+                    &["a", "b"],
+                    typs::func!(typs::func!(a(), b()), stream(a()), stream(b())),
+                ),
+                &Val::op2(|f, s| Ok(Val::Stream(stream_map(f, cast!(s, Val::Stream))))),
+            )
+            .insert_val(
+                "take",
+                &Typ::forall(&["a"], typs::func!(Typ::atom("Int"), stream(a()), stream(a()))),
+                &Val::op2(|n, s| Ok(Val::Stream(take(cast!(n, Val::Int), cast!(s, Val::Stream))))),
+            )
+            .insert_val(
+                "drop",
+                &Typ::forall(&["a"], typs::func!(Typ::atom("Int"), stream(a()), stream(a()))),
+                &Val::op2(|n, s| {
+                    let n = cast!(n, Val::Int);
+                    let s = cast!(s, Val::Stream);
+
+                    Ok(Val::Stream(Stream::delay(move || {
+                        let mut s = s.clone();
+                        for _ in 0..n {
+                            match s.force()? {
+                                Some((_, rest)) => s = rest,
+                                None => return Ok(None),
+                            }
+                        }
+
+                        s.force()
+                    })))
+                }),
+            )
+            // forces the whole stream, which must be finite
+            .insert_val(
+                "toList",
+                &Typ::forall(&["a"], typs::func!(stream(a()), Typ::list(a()))),
+                &Val::op1(|s| {
+                    let mut s = cast!(s, Val::Stream);
+
+                    let mut items = vec![];
+                    while let Some((x, rest)) = s.force()? {
+                        items.push(x);
+                        s = rest;
+                    }
+
+                    Ok(Val::List(items.into_iter().collect()))
+                }),
+            )
+    }
+}
+
+// checks an index into an array of the given length
+fn index(i: i32, length: usize) -> ValResult<usize> {
+    if i < 0 || i as usize >= length {
+        Err(ValError::OutOfBounds { index: i, length })
+    } else {
+        Ok(i as usize)
+    }
+}
+
+fn iterate(f: Val, x: Val) -> Stream {
+    let tail = {
+        let x = x.clone();
+        Stream::delay(move || iterate(f.clone(), f.apply(x.clone())?).force())
+    };
+
+    Stream::cons(x, tail)
+}
+
+fn unfold(f: Val, seed: Val) -> Stream {
+    Stream::delay(move || match f.apply(seed.clone())? {
+        Val::Con(name, args) if name == "Some" => {
+            let pair = cast!(args[0].clone(), Val::Pair);
+            Ok(Some((pair.0.clone(), unfold(f.clone(), pair.1.clone()))))
+        }
+        _ => Ok(None),
+    })
+}
+
+fn stream_map(f: Val, s: Stream) -> Stream {
+    Stream::delay(move || match s.force()? {
+        Some((x, rest)) => Ok(Some((f.apply(x)?, stream_map(f.clone(), rest)))),
+        None => Ok(None),
+    })
+}
+
+fn take(n: i32, s: Stream) -> Stream {
+    if n <= 0 {
+        return Stream::nil();
     }
+
+    Stream::delay(move || match s.force()? {
+        Some((x, rest)) => Ok(Some((x, take(n - 1, rest)))),
+        None => Ok(None),
+    })
+}
+
+fn array(a: Typ) -> Typ {
+    Typ::con("Array", vec![a])
+}
+
+fn map(k: Typ, v: Typ) -> Typ {
+    Typ::con("Map", vec![k, v])
+}
+
+fn set(a: Typ) -> Typ {
+    Typ::con("Set", vec![a])
+}
+
+fn stream(a: Typ) -> Typ {
+    Typ::con("Stream", vec![a])
+}
+
+fn option(a: Typ) -> Typ {
+    Typ::con("Option", vec![a])
+}
+
+fn result(e: Typ, a: Typ) -> Typ {
+    Typ::con("Result", vec![e, a])
+}
+
+// type variables used by the built-ins' types
+fn a() -> Typ {
+    Typ::var("a")
+}
+
+fn b() -> Typ {
+    Typ::var("b")
+}
+
+fn e() -> Typ {
+    Typ::var("e")
+}
+
+fn k() -> Typ {
+    Typ::var("k")
+}
+
+fn v() -> Typ {
+    Typ::var("v")
+}
+
+// function performing the effects of the callback it was given
+fn effectful(from: Typ, to: Typ) -> Typ {
+    Typ::func_eff(from, to, Typ::var(CALLBACK_ROW))
+}
+
+// the continuation shares the effects e of the function it's passed to
+fn callcc() -> Typ {
+    let cont = |from, to| Typ::func_eff(from, to, e());
+    Typ::forall(&["a", "b", "e"], cont(cont(cont(a(), b()), a()), a()))
 }
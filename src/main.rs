@@ -1,41 +1,66 @@
-use std::io::Read;
-use fun::{ast::Decl, terms::Term, typs, typs::Typ, vals::Val, ProgramContext};
-
-#[macro_use]
-extern crate lalrpop_util;
-lalrpop_mod!(pub grammar);
+use std::io::{IsTerminal, Read};
+use fun::{ast::Decl, grammar, lexer::Lexer, steps::Order, terms::Term, typs::{Typ, TypError}, vals::{Evaluator, Val, ValResult}, ProgramContext};
 
 fn main() -> std::io::Result<()> {
     let args: Vec<_> = std::env::args().collect();
 
+    let (flags, files): (Vec<_>, Vec<_>) = args[1..].iter().partition(|arg| arg.starts_with("--"));
+
+    let mut evaluator = Evaluator::Direct;
+    let mut trace = None;
+    let mut normalize = false;
+    let mut doc = false;
+    for flag in flags {
+        match flag.as_str() {
+            "--cps" => evaluator = Evaluator::Cps,
+            "--lazy" => evaluator = Evaluator::Lazy,
+            "--trace" | "--trace=value" => trace = Some(Order::ByValue),
+            "--trace=name" => trace = Some(Order::ByName),
+            "--trace=normal" => trace = Some(Order::Normal),
+            "--normalize" => normalize = true,
+            "--doc" => doc = true,
+            _ => usage(&args[0]),
+        }
+    }
+
     let mut src = String::new();
-    match args.len() {
-        0 => unreachable!(),
-        2 => {
-            if &args[1] == "-" {
+    match files.as_slice() {
+        [file] => {
+            if file.as_str() == "-" {
                 let mut stdin = std::io::stdin();
                 stdin.read_to_string(&mut src)?;
             } else {
-                src = std::fs::read_to_string(&args[1])?;
+                src = std::fs::read_to_string(file)?;
             }
         }
-        _ => {
-            eprintln!("usage: {} [file]", args[0]);
-            std::process::exit(1);
-        }
+        _ => usage(&args[0]),
     };
 
-    handle(&src);
+    handle(&src, evaluator, trace, normalize, doc);
 
     Ok(())
 }
 
-fn handle(src: &str) {
-    let program = grammar::ProgramParser::new().parse(src).unwrap();
+fn usage(name: &str) -> ! {
+    eprintln!("usage: {} [--cps | --lazy | --trace[=value|name|normal]] [--normalize] [--doc] [file]", name);
+    std::process::exit(1);
+}
 
-    let mut ctx = ProgramContext::default().insert_val(
+// with normalize, prints the normal form of each definition instead of
+// running main, and with doc the declarations along with their types and
+// doc comments, without evaluating anything
+fn handle(src: &str, evaluator: Evaluator, trace: Option<Order>, normalize: bool, doc: bool) {
+    let program = match grammar::ProgramParser::new().parse(Lexer::new(src)) {
+        Ok(program) => program,
+        Err(why) => {
+            eprintln!("Parse error: {}", why);
+            return;
+        }
+    };
+
+    let mut ctx = ProgramContext::default().with_evaluator(evaluator).insert_val(
         "print",
-        &typs::func!(Typ::atom("Int"), Typ::atom("Unit")),
+        &Typ::forall(&["a"], Typ::func_eff(Typ::var("a"), Typ::atom("Unit"), Typ::io())),
         &Val::native(|n| {
             println!("{}", n);
             Ok(Val::Unit)
@@ -44,63 +69,161 @@ fn handle(src: &str) {
 
     let mut main = None;
 
-    for decl in &program {
-        match decl {
+    // after the first failing definition the rest of the program is still
+    // type checked, but no longer evaluated
+    let mut failed = false;
+
+    let names: Vec<&str> = program
+        .iter()
+        .flat_map(|item| match &item.decl {
+            Decl::Let(name, _) | Decl::Exception(name, _) => vec![name.as_str()],
+            Decl::Effect(_, ops) => ops.iter().map(|(op, _)| op.as_str()).collect(),
+            _ => vec![],
+        })
+        .collect();
+
+    for item in &program {
+        match &item.decl {
             Decl::Type(_, _) => {
                 todo!();
             }
+            Decl::Exception(name, args) => {
+                if ctx.get_typ(name).is_some() {
+                    eprintln!("Exception defined twice at the global scope: {}", name);
+                    failed = true;
+                    continue;
+                }
+
+                match ctx.insert_exception(name, args) {
+                    Ok(new) => ctx = new,
+                    Err(why) => {
+                        report_typ_error(name, &why);
+                        failed = true;
+                        ctx = ctx.insert_typ(name, &Typ::Unknown);
+                    }
+                }
+            }
+            Decl::Effect(name, ops) => {
+                if let Some((op, _)) = ops.iter().find(|(op, _)| ctx.get_typ(op).is_some()) {
+                    eprintln!("Operation defined twice at the global scope: {}", op);
+                    failed = true;
+                    continue;
+                }
+
+                match ctx.insert_effect(name, ops) {
+                    Ok(new) => ctx = new,
+                    Err(why) => {
+                        report_typ_error(name, &why);
+                        failed = true;
+                        for (op, _) in ops {
+                            ctx = ctx.insert_typ(op, &Typ::Unknown);
+                        }
+                    }
+                }
+            }
             Decl::Let(name, term) => {
-                if let Some(_) = ctx.get_val(&name) {
+                if ctx.get_typ(name).is_some() {
                     eprintln!("Term defined twice at the global scope: {}", name);
-                    return;
+                    failed = true;
+                    continue;
                 }
 
-                if let Some(_) = main {
+                if main.is_some() {
                     eprintln!("Term defined after main: {}", name);
-                    return;
+                    failed = true;
+                    continue;
                 }
 
                 if name == "main" {
                     main = Some(term);
-                } else {
-                    ctx = match ctx.insert_term(&name, &term) {
-                        Ok(ctx) => ctx,
+                    continue;
+                }
+
+                ctx = match ctx.typ(term) {
+                    Err(why) => {
+                        let later = &names[names.iter().position(|n| n == name).unwrap() + 1..];
+                        report_typ_error(name, &why.declared_later(later));
+                        failed = true;
+                        ctx.insert_typ(name, &Typ::Unknown)
+                    }
+                    Ok(typ) if failed || doc => ctx.insert_typ(name, &typ),
+                    Ok(typ) => match eval(&ctx, term, trace) {
+                        Ok(val) => {
+                            if normalize {
+                                println!("{} = {}", name, ctx.reify(&val, &typ));
+                            }
+                            ctx.insert_val(name, &typ, &val)
+                        }
                         Err(why) => {
-                            eprintln!("{:?}", why);
-                            return;
+                            eprintln!("Eval error in {}: {}", name, why);
+                            failed = true;
+                            ctx.insert_typ(name, &typ)
                         }
-                    }
-                }
+                    },
+                };
             }
         }
     }
 
+    if doc {
+        let docs: Vec<_> = program.iter().filter_map(|item| ctx.describe(item)).collect();
+        println!("{}", docs.join("\n\n"));
+        return;
+    }
+
     match main {
         None => eprintln!("No main function defined"),
         Some(main) => {
-            match ctx.typ(&main) {
-                Ok(typ) => {
-                    if typ != Typ::func(Typ::atom("Unit"), Typ::atom("Unit")) {
-                        eprintln!(
-                            "Invalid type defined for main ({}): it must have type Unit -> Unit",
-                            typ
-                        );
-                        return;
-                    }
-                }
-                Err(why) => {
-                    eprintln!("Type error: {}", why);
-                    return;
-                }
+            // main alone may do IO
+            let main_typ = Typ::func_eff(Typ::atom("Unit"), Typ::atom("Unit"), Typ::io());
+            if let Err(why) = ctx.check(main, &main_typ) {
+                report_typ_error("main", &why);
+                return;
             }
 
-            match ctx.eval(&Term::app(main.clone(), Term::var("Unit"))) {
-                Err(why) => {
-                    eprintln!("Eval error: {}", why);
-                    return;
-                }
-                _ => {}
+            if failed || normalize {
+                return;
+            }
+
+            if let Err(why) = eval(&ctx, &Term::app(main.clone(), Term::var("Unit")), trace) {
+                eprintln!("Eval error: {}", why);
+            }
+        }
+    }
+}
+
+// evaluates the term, printing each step of its reduction when tracing
+fn eval(ctx: &ProgramContext, term: &Term, trace: Option<Order>) -> ValResult<Val> {
+    let order = match trace {
+        Some(order) => order,
+        None => return ctx.eval(term),
+    };
+
+    // the redex is underlined on a terminal
+    let (open, close) = match std::io::stdout().is_terminal() {
+        true => ("\x1b[4m", "\x1b[0m"),
+        false => ("⟦", "⟧"),
+    };
+
+    let mut last = None;
+    let val = ctx.trace(term, order, |step| {
+        println!("{}", step.highlight(open, close));
+        last = Some(step.term());
+    });
+    if let Some(term) = last {
+        println!("{}", term);
+    }
+
+    val
+}
+
+fn report_typ_error(name: &str, why: &TypError) {
+    match why {
+        TypError::Many(whys) => {
+            for why in whys {
+                report_typ_error(name, why);
             }
         }
+        _ => eprintln!("Type error in {}: {}", name, why),
     }
 }
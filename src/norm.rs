@@ -0,0 +1,626 @@
+use crate::steps::same;
+use crate::terms::Pat;
+use crate::terms::Stmt;
+use crate::terms::Term;
+use crate::typs::Typ;
+use crate::vals::{List, Strategy, Val, ValContext};
+
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+// normalisation by evaluation
+//
+// a function is read back as a term by applying it to a fresh variable and
+// evaluating its body, where whatever needs the value of the variable gets
+// stuck as a neutral term. only functions without effects are normalised,
+// the body being run before they're ever called; any other function, or one
+// whose body turns out to do something which can't be done ahead of time,
+// such as raising an exception or updating a reference, is read back as it
+// was written, with the values it captured in place of their names
+
+// a value which may depend on the variables of the functions being
+// normalised
+#[derive(Clone)]
+enum Sem {
+    Val(Val),
+    Abs {
+        env: Env,
+        param: String,
+        param_typ: Option<Typ>,
+        body: Rc<Term>,
+    },
+    // built-in function applied to the arguments in the term
+    Prim(Term, Val),
+    List(Vec<Sem>),
+    Pair(Rc<(Sem, Sem)>),
+    // term stuck on a variable
+    Neutral(Term),
+}
+
+#[derive(Clone)]
+struct Env {
+    vals: ValContext,
+    // shadow vals
+    sems: crate::Map<String, Sem>,
+}
+
+impl Env {
+    fn new(vals: &ValContext) -> Self {
+        Env {
+            vals: vals.clone(),
+            sems: crate::Map::new(),
+        }
+    }
+
+    fn bind(&self, name: &str, sem: Sem) -> Self {
+        Env {
+            vals: self.vals.clone(),
+            sems: self.sems.insert(name.to_string(), sem),
+        }
+    }
+}
+
+impl Val {
+    // the value of the given type as a term, functions being in β-normal
+    // form where possible
+    pub fn reify(&self, typ: &Typ, val_ctx: &ValContext) -> Term {
+        let mut normaliser = Normaliser {
+            val_ctx,
+            bound: BTreeSet::new(),
+            impure: false,
+        };
+
+        normaliser.reify(&Sem::Val(self.clone()), typ)
+    }
+}
+
+struct Normaliser<'a> {
+    // the globals, which are referred to by name
+    val_ctx: &'a ValContext,
+    // variables introduced so far, never reused
+    bound: BTreeSet<String>,
+    // whether the function being normalised did something which can't be
+    // done before it's called
+    impure: bool,
+}
+
+impl Normaliser<'_> {
+    fn reify(&mut self, sem: &Sem, typ: &Typ) -> Term {
+        match (sem, typ) {
+            (_, Typ::Forall { typ, .. }) => self.reify(sem, typ),
+            (_, Typ::Func { from, to, eff }) if is_pure(eff) => {
+                let hint = match sem {
+                    Sem::Abs { param, .. } | Sem::Val(Val::Abs { param, .. }) if param != "_" => {
+                        param.as_str()
+                    }
+                    _ => "x",
+                };
+                let bound = self.bound.clone();
+                let x = self.fresh(hint);
+
+                let impure = std::mem::replace(&mut self.impure, false);
+                let body = self.apply(sem.clone(), Sem::Neutral(Term::var(&x)));
+                if std::mem::replace(&mut self.impure, impure) {
+                    self.bound = bound;
+                    return annotate(self.read_back(sem), from);
+                }
+
+                Term::abs(&x, (**from).clone(), self.reify(&body, to))
+            }
+            (_, Typ::Func { from, .. }) => annotate(self.read_back(sem), from),
+            (Sem::Val(Val::List(list)), Typ::Con { name, args }) if name == "List" => Term::List(
+                list.iter()
+                    .map(|val| self.reify(&Sem::Val(val.clone()), &args[0]))
+                    .collect(),
+            ),
+            (Sem::List(items), Typ::Con { name, args }) if name == "List" => Term::List(
+                items.iter().map(|item| self.reify(item, &args[0])).collect(),
+            ),
+            (Sem::Val(Val::Pair(pair)), Typ::Con { name, args }) if name == "Pair" => Term::pair(
+                self.reify(&Sem::Val(pair.0.clone()), &args[0]),
+                self.reify(&Sem::Val(pair.1.clone()), &args[1]),
+            ),
+            (Sem::Pair(pair), Typ::Con { name, args }) if name == "Pair" => Term::pair(
+                self.reify(&pair.0, &args[0]),
+                self.reify(&pair.1, &args[1]),
+            ),
+            _ => self.read_back(sem),
+        }
+    }
+
+    // the value as a term, without normalising the functions in it
+    fn read_back(&mut self, sem: &Sem) -> Term {
+        match sem {
+            Sem::Val(val) => self.quote(val),
+            Sem::Abs {
+                env,
+                param,
+                param_typ,
+                body,
+            } => {
+                let x = self.fresh(if param == "_" { "x" } else { param });
+                let env = env.bind(param, Sem::Neutral(Term::var(&x)));
+
+                Term::Abs {
+                    param_name: x,
+                    param_typ: param_typ.clone(),
+                    body: Box::new(self.residual(body, &env)),
+                }
+            }
+            Sem::Prim(term, _) | Sem::Neutral(term) => term.clone(),
+            Sem::List(items) => Term::List(items.iter().map(|item| self.read_back(item)).collect()),
+            Sem::Pair(pair) => Term::pair(self.read_back(&pair.0), self.read_back(&pair.1)),
+        }
+    }
+
+    fn quote(&mut self, val: &Val) -> Term {
+        if let Some(name) = self.name(val) {
+            return Term::var(&name);
+        }
+
+        match val {
+            Val::Abs {
+                val_ctx,
+                param,
+                body,
+                ..
+            } => self.read_back(&Sem::Abs {
+                env: Env::new(val_ctx),
+                param: param.clone(),
+                param_typ: None,
+                body: body.clone(),
+            }),
+            Val::Thunk(_) => match val.force() {
+                Ok(val) => self.quote(&val),
+                Err(_) => Term::var(&val.to_string()),
+            },
+
+            Val::Bool(true) => Term::var("True"),
+            Val::Bool(false) => Term::var("False"),
+            Val::Int(i) => Term::Int(*i),
+            Val::Unit => Term::var("Unit"),
+
+            Val::List(list) => Term::List(list.iter().map(|val| self.quote(val)).collect()),
+            Val::Pair(pair) => Term::pair(self.quote(&pair.0), self.quote(&pair.1)),
+            Val::Con(name, args) => args.iter().fold(Term::var(name), |con, arg| {
+                Term::app(con, self.quote(arg))
+            }),
+            // maps and sets are rebuilt by inserting into the empty one
+            Val::Map(map) => map.iter().fold(Term::var("mapEmpty"), |map, (key, val)| {
+                let insert = Term::app(Term::var("mapInsert"), self.quote(key.val()));
+                Term::app(Term::app(insert, self.quote(val)), map)
+            }),
+            Val::Set(set) => set.iter().fold(Term::var("setEmpty"), |set, key| {
+                Term::app(Term::app(Term::var("setInsert"), self.quote(key.val())), set)
+            }),
+
+            Val::Op(op) => Term::var(op),
+            _ => Term::var(&val.to_string()),
+        }
+    }
+
+    // the global the value is bound to, if any
+    fn name(&self, val: &Val) -> Option<String> {
+        self.val_ctx
+            .iter()
+            .find(|(_, global)| same(global, val))
+            .map(|(name, _)| name.clone())
+    }
+
+    // a variable which is neither a global nor bound already
+    fn fresh(&mut self, hint: &str) -> String {
+        let taken = |name: &str| self.bound.contains(name) || self.val_ctx.get(name).is_some();
+
+        let name = match taken(hint) {
+            false => hint.to_string(),
+            true => (1..)
+                .map(|i| format!("{}{}", hint, i))
+                .find(|name| !taken(name))
+                .unwrap(),
+        };
+        self.bound.insert(name.clone());
+
+        name
+    }
+
+    fn eval(&mut self, term: &Term, env: &Env) -> Sem {
+        match term {
+            Term::Var { name } => {
+                if let Some(sem) = env.sems.get(name) {
+                    return sem.clone();
+                }
+
+                match env.vals.get(name).map(Val::force) {
+                    Some(Ok(val)) => Sem::Val(val),
+                    Some(Err(_)) => {
+                        self.impure = true;
+                        Sem::Neutral(term.clone())
+                    }
+                    None => Sem::Neutral(term.clone()),
+                }
+            }
+            Term::Abs {
+                param_name,
+                param_typ,
+                body,
+            } => Sem::Abs {
+                env: env.clone(),
+                param: param_name.clone(),
+                param_typ: param_typ.clone(),
+                body: Rc::new((**body).clone()),
+            },
+            Term::App { func, arg } => {
+                let func = self.eval(func, env);
+                let arg = self.eval(arg, env);
+                self.apply(func, arg)
+            }
+            Term::Compose { first, second } => {
+                let first = self.eval(first, env);
+                let second = self.eval(second, env);
+
+                Sem::Abs {
+                    env: Env::new(&ValContext::new())
+                        .bind("first", first)
+                        .bind("second", second),
+                    param: "x".to_string(),
+                    param_typ: None,
+                    body: Rc::new(Term::app(
+                        Term::var("second"),
+                        Term::app(Term::var("first"), Term::var("x")),
+                    )),
+                }
+            }
+
+            Term::Int(i) => Sem::Val(Val::Int(*i)),
+
+            Term::List(items) => {
+                let items: Vec<_> = items.iter().map(|item| self.eval(item, env)).collect();
+                list(items)
+            }
+            Term::Cons { head, tail } => {
+                let head = self.eval(head, env);
+                match self.eval(tail, env) {
+                    Sem::Val(Val::List(tail)) => {
+                        let mut items = vec![head];
+                        items.extend(tail.iter().cloned().map(Sem::Val));
+                        list(items)
+                    }
+                    Sem::List(tail) => {
+                        let mut items = vec![head];
+                        items.extend(tail);
+                        Sem::List(items)
+                    }
+                    tail => Sem::Neutral(Term::cons(self.read_back(&head), self.read_back(&tail))),
+                }
+            }
+            Term::Pair { fst, snd } => match (self.eval(fst, env), self.eval(snd, env)) {
+                (Sem::Val(fst), Sem::Val(snd)) => Sem::Val(Val::pair(fst, snd)),
+                (fst, snd) => Sem::Pair(Rc::new((fst, snd))),
+            },
+
+            Term::Ascribe { term, .. } => self.eval(term, env),
+            Term::Hole(_) => Sem::Neutral(term.clone()),
+
+            Term::If {
+                cond,
+                t_true,
+                t_false,
+            } => match self.eval(cond, env) {
+                Sem::Val(Val::Bool(true)) => self.eval(t_true, env),
+                Sem::Val(Val::Bool(false)) => self.eval(t_false, env),
+                cond => {
+                    let cond = self.read_back(&cond);
+                    let t_true = self.eval(t_true, env);
+                    let t_false = self.eval(t_false, env);
+                    Sem::Neutral(Term::if_(
+                        cond,
+                        self.read_back(&t_true),
+                        self.read_back(&t_false),
+                    ))
+                }
+            },
+            Term::Match { scrutinee, arms } => {
+                let scrutinee = self.eval(scrutinee, env);
+                for (pat, body) in arms {
+                    match self.bind(pat, &scrutinee) {
+                        Some(Some(binds)) => {
+                            let env = binds
+                                .into_iter()
+                                .fold(env.clone(), |env, (name, sem)| env.bind(&name, sem));
+                            return self.eval(body, &env);
+                        }
+                        Some(None) => continue,
+                        None => return self.stuck_match(&scrutinee, arms, env),
+                    }
+                }
+
+                // fails when run
+                self.impure = true;
+                Sem::Neutral(self.residual(term, env))
+            }
+            Term::Seq(stmts) => self.block(stmts, env),
+
+            // references, exceptions and effects are left for the function
+            // to do when called
+            _ => {
+                self.impure = true;
+                Sem::Neutral(self.residual(term, env))
+            }
+        }
+    }
+
+    fn apply(&mut self, func: Sem, arg: Sem) -> Sem {
+        match func {
+            Sem::Abs {
+                env, param, body, ..
+            } => self.eval(&body, &env.bind(&param, arg)),
+            Sem::Val(Val::Abs {
+                val_ctx,
+                param,
+                body,
+                ..
+            }) => self.eval(&body, &Env::new(&val_ctx).bind(&param, arg)),
+            Sem::Val(Val::Native(f)) => {
+                let native = Val::Native(f);
+                let head = self.quote(&native);
+                self.apply(Sem::Prim(head, native), arg)
+            }
+            // built-in functions only run on arguments known in full
+            Sem::Prim(head, native) => {
+                let term = Term::app(head, self.read_back(&arg));
+                match self.concrete(&arg) {
+                    Some(arg) => match native.apply(arg) {
+                        Ok(Val::Native(f)) => Sem::Prim(term, Val::Native(f)),
+                        Ok(val) => Sem::Val(val),
+                        Err(_) => {
+                            self.impure = true;
+                            Sem::Neutral(term)
+                        }
+                    },
+                    None => {
+                        // stuck on mutable state rather than on a variable
+                        if !self.inert(&arg) {
+                            self.impure = true;
+                        }
+                        Sem::Neutral(term)
+                    }
+                }
+            }
+            func => Sem::Neutral(Term::app(self.read_back(&func), self.read_back(&arg))),
+        }
+    }
+
+    fn block(&mut self, stmts: &[Stmt], env: &Env) -> Sem {
+        let mut env = env.clone();
+        let mut last = Sem::Val(Val::Unit);
+        for (i, stmt) in stmts.iter().enumerate() {
+            match stmt {
+                Stmt::Term(term) => last = self.eval(term, &env),
+                Stmt::Let(name, term) => {
+                    last = self.eval(term, &env);
+                    env = env.bind(name, last.clone());
+                }
+                // loops and jumps are left for the function to do
+                _ => {
+                    self.impure = true;
+                    return Sem::Neutral(self.residual(&Term::Seq(stmts[i..].to_vec()), &env));
+                }
+            }
+        }
+
+        last
+    }
+
+    // matches the value against the pattern, returning the variables it
+    // binds; None if the value isn't known well enough
+    fn bind(&mut self, pat: &Pat, sem: &Sem) -> Option<Option<Vec<(String, Sem)>>> {
+        match (pat, sem) {
+            (Pat::Wild, _) => Some(Some(vec![])),
+            (Pat::Var(name), _) => Some(Some(vec![(name.clone(), sem.clone())])),
+            (_, Sem::Val(val)) => Some(pat.bind(val, self.val_ctx).map(|val_ctx| {
+                pat.vars()
+                    .into_iter()
+                    .map(|var| {
+                        let val = val_ctx.get(&var).unwrap().clone();
+                        (var, Sem::Val(val))
+                    })
+                    .collect()
+            })),
+            (Pat::Nil, Sem::List(items)) => Some(items.is_empty().then(Vec::new)),
+            (Pat::Cons(head, tail), Sem::List(items)) => match items.split_first() {
+                None => Some(None),
+                Some((x, xs)) => self.bind_all(&[(head, x), (tail, &Sem::List(xs.to_vec()))]),
+            },
+            (Pat::Pair(fst, snd), Sem::Pair(pair)) => {
+                self.bind_all(&[(fst, &pair.0), (snd, &pair.1)])
+            }
+            _ => None,
+        }
+    }
+
+    fn bind_all(&mut self, pairs: &[(&Pat, &Sem)]) -> Option<Option<Vec<(String, Sem)>>> {
+        let mut binds = vec![];
+        let mut known = true;
+        for (pat, sem) in pairs {
+            match self.bind(pat, sem) {
+                Some(None) => return Some(None),
+                Some(Some(more)) => binds.extend(more),
+                None => known = false,
+            }
+        }
+
+        known.then_some(Some(binds))
+    }
+
+    // the match, with each arm normalised under fresh names for the
+    // variables of its pattern
+    fn stuck_match(&mut self, scrutinee: &Sem, arms: &[(Pat, Term)], env: &Env) -> Sem {
+        let scrutinee = self.read_back(scrutinee);
+        let arms = arms
+            .iter()
+            .map(|(pat, body)| {
+                let mut pat = pat.clone();
+                let mut env = env.clone();
+                for var in pat.vars() {
+                    let x = self.fresh(&var);
+                    pat = pat.rename(&var, &x);
+                    env = env.bind(&var, Sem::Neutral(Term::var(&x)));
+                }
+
+                let body = self.eval(body, &env);
+                (pat, self.read_back(&body))
+            })
+            .collect();
+
+        Sem::Neutral(Term::match_(scrutinee, arms))
+    }
+
+    // the term with the values of its free variables in place of their
+    // names, globals aside
+    fn residual(&mut self, term: &Term, env: &Env) -> Term {
+        let mut binds = vec![];
+        for var in term.free_vars() {
+            let by = match (env.sems.get(&var), env.vals.get(&var)) {
+                (Some(sem), _) => self.read_back(sem),
+                (None, Some(val)) => match self.val_ctx.get(&var) {
+                    Some(global) if same(global, val) => continue,
+                    _ => self.quote(val),
+                },
+                (None, None) => continue,
+            };
+
+            match &by {
+                Term::Var { name } if *name == var => {}
+                _ => binds.push((var, by)),
+            }
+        }
+
+        term.subst_all(&binds)
+    }
+
+    // the value, if it doesn't depend on any variable nor on mutable state
+    fn concrete(&self, sem: &Sem) -> Option<Val> {
+        match sem {
+            Sem::Val(val) => self.inert_val(val).then(|| val.clone()),
+            Sem::Abs {
+                env, param, body, ..
+            } => {
+                let mut val_ctx = env.vals.clone();
+                for var in body.free_vars() {
+                    if var == *param {
+                        continue;
+                    }
+
+                    match env.sems.get(&var) {
+                        Some(sem) => val_ctx = val_ctx.insert(var, self.concrete(sem)?),
+                        None => {
+                            if !env.vals.get(&var).is_none_or(|val| self.inert_val(val)) {
+                                return None;
+                            }
+                        }
+                    }
+                }
+
+                Some(Val::Abs {
+                    val_ctx,
+                    param: param.clone(),
+                    body: body.clone(),
+                    strategy: Strategy::ByValue,
+                })
+            }
+            Sem::Prim(_, val) => Some(val.clone()),
+            Sem::List(items) => {
+                let items = items
+                    .iter()
+                    .map(|item| self.concrete(item))
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Val::List(items.into_iter().rev().fold(List::nil(), |tail, head| {
+                    List::cons(head, tail)
+                })))
+            }
+            Sem::Pair(pair) => Some(Val::pair(self.concrete(&pair.0)?, self.concrete(&pair.1)?)),
+            Sem::Neutral(_) => None,
+        }
+    }
+
+    // whether the value is free of mutable state, so that a built-in
+    // function can be run on it ahead of time
+    fn inert(&self, sem: &Sem) -> bool {
+        match sem {
+            Sem::Val(val) => self.inert_val(val),
+            Sem::Abs {
+                env, param, body, ..
+            } => body.free_vars()
+                .iter()
+                .filter(|var| *var != param)
+                .all(|var| match env.sems.get(var) {
+                    Some(sem) => self.inert(sem),
+                    None => env.vals.get(var).is_none_or(|val| self.inert_val(val)),
+                }),
+            Sem::List(items) => items.iter().all(|item| self.inert(item)),
+            Sem::Pair(pair) => self.inert(&pair.0) && self.inert(&pair.1),
+            Sem::Prim(_, _) | Sem::Neutral(_) => true,
+        }
+    }
+
+    fn inert_val(&self, val: &Val) -> bool {
+        match val {
+            Val::Abs {
+                val_ctx,
+                param,
+                body,
+                ..
+            } => body.free_vars()
+                .iter()
+                .filter(|var| *var != param)
+                .all(|var| val_ctx.get(var).is_none_or(|val| self.inert_val(val))),
+            Val::Native(_) | Val::Bool(_) | Val::Int(_) | Val::Unit | Val::Set(_) => true,
+            Val::List(list) => list.iter().all(|val| self.inert_val(val)),
+            Val::Pair(pair) => self.inert_val(&pair.0) && self.inert_val(&pair.1),
+            Val::Con(_, args) => args.iter().all(|val| self.inert_val(val)),
+            Val::Map(map) => map.values().all(|val| self.inert_val(val)),
+            Val::Thunk(_)
+            | Val::Ref(_)
+            | Val::Array(_)
+            | Val::Stream(_)
+            | Val::Op(_)
+            | Val::Cont(_)
+            | Val::CallCC
+            | Val::Escape { .. } => false,
+        }
+    }
+}
+
+// whether the row of effects is empty
+fn is_pure(eff: &Typ) -> bool {
+    matches!(eff, Typ::Row { effs, tail: None } if effs.is_empty())
+}
+
+// the function with the type of its parameter, if it wasn't written
+fn annotate(func: Term, from: &Typ) -> Term {
+    match func {
+        Term::Abs {
+            param_name,
+            param_typ: None,
+            body,
+        } => Term::Abs {
+            param_name,
+            param_typ: Some(from.clone()),
+            body,
+        },
+        func => func,
+    }
+}
+
+fn list(items: Vec<Sem>) -> Sem {
+    match items.iter().all(|item| matches!(item, Sem::Val(_))) {
+        true => Sem::Val(Val::List(items.into_iter().rev().fold(
+            List::nil(),
+            |tail, head| match head {
+                Sem::Val(head) => List::cons(head, tail),
+                _ => unreachable!(),
+            },
+        ))),
+        false => Sem::List(items),
+    }
+}
@@ -0,0 +1,730 @@
+use crate::subst::fresh;
+use crate::terms::Pat;
+use crate::terms::Stmt;
+use crate::terms::Term;
+use crate::vals::{Val, ValContext, ValError, ValResult};
+
+use std::rc::Rc;
+
+// small-step reduction
+//
+// the term is rewritten one redex at a time, substituting arguments for
+// parameters. built-in functions applied to values are a single step, and
+// so are the constructs without reduction rules of their own (loops,
+// references, handlers), which are evaluated by Term::eval. values that
+// can't be written as terms, e.g. references, are bound to fresh names such
+// as ref#1 in the context
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Order {
+    // arguments and let bound terms are reduced to values first
+    ByValue,
+    // arguments and let bound terms are substituted as they are
+    ByName,
+    // by name, then also under functions until no redex is left
+    Normal,
+}
+
+// a redex, what it reduces to, and the term around it
+pub struct Step {
+    context: Box<dyn Fn(Term) -> Term>,
+    pub redex: Term,
+    pub contractum: Term,
+}
+
+impl Step {
+    fn new(redex: &Term, contractum: Term) -> Self {
+        Step {
+            context: Box::new(|term| term),
+            redex: redex.clone(),
+            contractum,
+        }
+    }
+
+    // the same step, taken inside a larger term
+    fn within<F: Fn(Term) -> Term + 'static>(self, f: F) -> Self {
+        let context = self.context;
+
+        Step {
+            context: Box::new(move |term| f(context(term))),
+            redex: self.redex,
+            contractum: self.contractum,
+        }
+    }
+
+    // the whole term after the step
+    pub fn term(&self) -> Term {
+        (self.context)(self.contractum.clone())
+    }
+
+    // the whole term before the step, with the redex between open and close
+    pub fn highlight(&self, open: &str, close: &str) -> String {
+        let redex = format!("{}{}{}", open, self.redex, close);
+        (self.context)(Term::var(&redex)).to_string()
+    }
+}
+
+impl Term {
+    // the next step of the reduction, or None once the term is a value (in
+    // normal order, a normal form)
+    pub fn step(&self, order: Order, val_ctx: &mut ValContext) -> ValResult<Option<Step>> {
+        let mut reducer = Reducer {
+            order,
+            val_ctx,
+            pure: false,
+            bound: vec![],
+        };
+
+        match reducer.step(self)? {
+            None if order == Order::Normal => {
+                reducer.pure = true;
+                reducer.step(self)
+            }
+            step => Ok(step),
+        }
+    }
+}
+
+struct Reducer<'a> {
+    order: Order,
+    val_ctx: &'a mut ValContext,
+    // reducing inside values, where only the redexes without effects are
+    // reduced, as the values may be functions not called yet
+    pure: bool,
+    // parameters of the functions reduced inside
+    bound: Vec<String>,
+}
+
+impl Reducer<'_> {
+    fn step(&mut self, term: &Term) -> ValResult<Option<Step>> {
+        match term {
+            Term::Var { name } => self.unfold(term, name),
+            Term::Abs {
+                param_name,
+                param_typ,
+                body,
+            } => {
+                let (param_name_, param_typ) = (param_name.clone(), param_typ.clone());
+                self.under(std::slice::from_ref(param_name), body, move |body| {
+                    Term::Abs {
+                        param_name: param_name_.clone(),
+                        param_typ: param_typ.clone(),
+                        body: Box::new(body),
+                    }
+                })
+            }
+            Term::App { func, arg } => self.app(term, func, arg),
+            Term::Compose { first, second } => {
+                let second_ = (**second).clone();
+                if let Some(step) = self.sub(term, first, move |first| {
+                    Term::compose(first, second_.clone())
+                })? {
+                    return Ok(Some(step));
+                }
+                let first_ = (**first).clone();
+                if let Some(step) = self.sub(term, second, move |second| {
+                    Term::compose(first_.clone(), second)
+                })? {
+                    return Ok(Some(step));
+                }
+
+                let mut avoid = first.free_vars();
+                avoid.extend(second.free_vars());
+                let x = fresh("x", &avoid);
+                let body = Term::app(
+                    (**second).clone(),
+                    Term::app((**first).clone(), Term::var(&x)),
+                );
+
+                Ok(Some(Step::new(term, Term::abs_untyped(&x, body))))
+            }
+
+            Term::Int(_) => Ok(None),
+
+            Term::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let items = items.clone();
+                    let step = self.sub(term, item, move |item| {
+                        let mut items = items.clone();
+                        items[i] = item;
+                        Term::List(items)
+                    })?;
+                    if step.is_some() {
+                        return Ok(step);
+                    }
+                }
+
+                Ok(None)
+            }
+            Term::Cons { head, tail } => {
+                let tail_ = (**tail).clone();
+                if let Some(step) =
+                    self.sub(term, head, move |head| Term::cons(head, tail_.clone()))?
+                {
+                    return Ok(Some(step));
+                }
+                let head_ = (**head).clone();
+                if let Some(step) =
+                    self.sub(term, tail, move |tail| Term::cons(head_.clone(), tail))?
+                {
+                    return Ok(Some(step));
+                }
+
+                match &**tail {
+                    Term::List(items) => {
+                        let items = std::iter::once((**head).clone()).chain(items.iter().cloned());
+                        Ok(Some(Step::new(term, Term::List(items.collect()))))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Term::Pair { fst, snd } => {
+                let snd_ = (**snd).clone();
+                if let Some(step) = self.sub(term, fst, move |fst| Term::pair(fst, snd_.clone()))? {
+                    return Ok(Some(step));
+                }
+                let fst_ = (**fst).clone();
+                self.sub(term, snd, move |snd| Term::pair(fst_.clone(), snd))
+            }
+
+            Term::Ascribe { term: inner, .. } => Ok(Some(Step::new(term, (**inner).clone()))),
+            Term::Hole(_) => unreachable!(),
+
+            Term::Ref(inner) => {
+                if let Some(step) = self.sub(term, inner, Term::ref_)? {
+                    return Ok(Some(step));
+                }
+
+                self.atomic(term)
+            }
+            Term::Deref(inner) => {
+                if let Some(step) = self.sub(term, inner, Term::deref)? {
+                    return Ok(Some(step));
+                }
+
+                self.atomic(term)
+            }
+            Term::Assign { target, val } => {
+                let val_ = (**val).clone();
+                if let Some(step) = self.sub(term, target, move |target| {
+                    Term::assign(target, val_.clone())
+                })? {
+                    return Ok(Some(step));
+                }
+                let target_ = (**target).clone();
+                if let Some(step) =
+                    self.sub(term, val, move |val| Term::assign(target_.clone(), val))?
+                {
+                    return Ok(Some(step));
+                }
+
+                self.atomic(term)
+            }
+
+            Term::If {
+                cond,
+                t_true,
+                t_false,
+            } => {
+                let (t_true_, t_false_) = ((**t_true).clone(), (**t_false).clone());
+                let step = self.sub(term, cond, move |cond| {
+                    Term::if_(cond, t_true_.clone(), t_false_.clone())
+                })?;
+                if step.is_some() {
+                    return Ok(step);
+                }
+
+                match &**cond {
+                    Term::Var { name } if name == "True" => {
+                        Ok(Some(Step::new(term, (**t_true).clone())))
+                    }
+                    Term::Var { name } if name == "False" => {
+                        Ok(Some(Step::new(term, (**t_false).clone())))
+                    }
+                    _ => {
+                        let (cond_, t_false_) = ((**cond).clone(), (**t_false).clone());
+                        let step = self.under(&[], t_true, move |t_true| {
+                            Term::if_(cond_.clone(), t_true, t_false_.clone())
+                        })?;
+                        if step.is_some() {
+                            return Ok(step);
+                        }
+
+                        let (cond_, t_true_) = ((**cond).clone(), (**t_true).clone());
+                        self.under(&[], t_false, move |t_false| {
+                            Term::if_(cond_.clone(), t_true_.clone(), t_false)
+                        })
+                    }
+                }
+            }
+            Term::Match { scrutinee, arms } => {
+                let arms_ = arms.clone();
+                let step = self.sub(term, scrutinee, move |scrutinee| {
+                    Term::match_(scrutinee, arms_.clone())
+                })?;
+                if step.is_some() {
+                    return Ok(step);
+                }
+
+                for (pat, body) in arms {
+                    let mut binds = vec![];
+                    match pat.matches(scrutinee, &mut binds) {
+                        Some(true) => return Ok(Some(Step::new(term, body.subst_all(&binds)))),
+                        Some(false) => continue,
+                        None => return self.arms(scrutinee, arms),
+                    }
+                }
+
+                if self.pure {
+                    return self.arms(scrutinee, arms);
+                }
+                Err(ValError::NoMatch(scrutinee.eval(self.val_ctx)?))
+            }
+            Term::Seq(stmts) => self.block(term, stmts),
+
+            Term::Raise(exn) => self.sub(term, exn, Term::raise),
+            Term::Try { body, pat, handler } => {
+                let (pat_, handler_) = (pat.clone(), (**handler).clone());
+                if let Some(step) = self.step(body)? {
+                    return Ok(Some(step.within(move |body| {
+                        Term::try_(body, pat_.clone(), handler_.clone())
+                    })));
+                }
+                if self.pure {
+                    return Ok(None);
+                }
+
+                match &**body {
+                    Term::Raise(exn) => {
+                        let mut binds = vec![];
+                        match pat.matches(exn, &mut binds) {
+                            Some(true) => Ok(Some(Step::new(term, handler.subst_all(&binds)))),
+                            _ => Ok(Some(Step::new(term, (**body).clone()))),
+                        }
+                    }
+                    _ => Ok(Some(Step::new(term, (**body).clone()))),
+                }
+            }
+
+            Term::Perform { op, arg } => {
+                let op = op.clone();
+                if let Some(step) = self.sub(term, arg, move |arg| Term::perform(&op, arg))? {
+                    return Ok(Some(step));
+                }
+
+                self.atomic(term)
+            }
+            Term::Handle { .. } => self.atomic(term),
+        }
+    }
+
+    // steps the subterm, evaluated in place, or propagates the exception
+    // it raises
+    fn sub<F: Fn(Term) -> Term + 'static>(
+        &mut self,
+        term: &Term,
+        sub: &Term,
+        f: F,
+    ) -> ValResult<Option<Step>> {
+        if let Some(step) = self.step(sub)? {
+            return Ok(Some(step.within(f)));
+        }
+
+        match sub {
+            Term::Raise(_) if !self.pure => Ok(Some(Step::new(term, sub.clone()))),
+            _ => Ok(None),
+        }
+    }
+
+    // steps the subterm, not evaluated yet, where the variables are bound
+    fn under<F: Fn(Term) -> Term + 'static>(
+        &mut self,
+        vars: &[String],
+        sub: &Term,
+        f: F,
+    ) -> ValResult<Option<Step>> {
+        if !self.pure {
+            return Ok(None);
+        }
+
+        self.bound.extend(vars.iter().cloned());
+        let step = self.step(sub);
+        self.bound.truncate(self.bound.len() - vars.len());
+
+        Ok(step?.map(|step| step.within(f)))
+    }
+
+    // replaces a variable by its value, unless it's a value by itself, e.g.
+    // a built-in function
+    fn unfold(&mut self, term: &Term, name: &str) -> ValResult<Option<Step>> {
+        if self.bound.iter().any(|var| var == name) {
+            return Ok(None);
+        }
+
+        let val = match self.val_ctx.get(name) {
+            Some(val) => val.clone(),
+            None => return Ok(None),
+        };
+        match val {
+            Val::Abs { .. }
+            | Val::Thunk(_)
+            | Val::Bool(_)
+            | Val::Int(_)
+            | Val::Unit
+            | Val::List(_)
+            | Val::Pair(_)
+            | Val::Con(_, _) => match self.quote(&val)? {
+                Term::Var { name: unfolded } if unfolded == name => Ok(None),
+                unfolded => Ok(Some(Step::new(term, unfolded))),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    fn app(&mut self, term: &Term, func: &Term, arg: &Term) -> ValResult<Option<Step>> {
+        if let Term::Abs {
+            param_name, body, ..
+        } = func
+        {
+            if self.order == Order::ByValue {
+                let func_ = func.clone();
+                if let Some(step) = self.sub(term, arg, move |arg| Term::app(func_.clone(), arg))? {
+                    return Ok(Some(step));
+                }
+            }
+
+            // returning from the middle of the body can't be done once it's
+            // substituted into the term
+            if returns(body) {
+                if !self.pure {
+                    return self.atomic(term);
+                }
+            } else {
+                return Ok(Some(Step::new(term, body.subst(param_name, arg))));
+            }
+        }
+
+        let arg_ = arg.clone();
+        if let Some(step) = self.sub(term, func, move |func| Term::app(func, arg_.clone()))? {
+            return Ok(Some(step));
+        }
+        // built-in functions take their arguments by value
+        let func_ = func.clone();
+        if let Some(step) = self.sub(term, arg, move |arg| Term::app(func_.clone(), arg))? {
+            return Ok(Some(step));
+        }
+
+        if self.pure || func.is_con() {
+            return Ok(None);
+        }
+
+        let func = func.eval(self.val_ctx)?;
+        let arg = arg.eval(self.val_ctx)?;
+        match func.apply(arg) {
+            // a built-in function waiting for more arguments
+            Ok(Val::Native(_)) => Ok(None),
+            Ok(val) => Ok(Some(Step::new(term, self.quote(&val)?))),
+            Err(ValError::Raise(exn)) => Ok(Some(Step::new(term, Term::raise(self.quote(&exn)?)))),
+            Err(why) => Err(why),
+        }
+    }
+
+    // tries the arms of a match on a value they can't be matched against
+    // yet
+    fn arms(&mut self, scrutinee: &Term, arms: &[(Pat, Term)]) -> ValResult<Option<Step>> {
+        for (i, (pat, body)) in arms.iter().enumerate() {
+            let (scrutinee, arms) = (scrutinee.clone(), arms.to_vec());
+            let step = self.under(&pat.vars(), body, move |body| {
+                let mut arms = arms.clone();
+                arms[i].1 = body;
+                Term::match_(scrutinee.clone(), arms)
+            })?;
+            if step.is_some() {
+                return Ok(step);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn block(&mut self, term: &Term, stmts: &[Stmt]) -> ValResult<Option<Step>> {
+        let (first, rest) = match stmts.split_first() {
+            None => return Ok(Some(Step::new(term, Term::var("Unit")))),
+            Some(split) => split,
+        };
+
+        let rest_ = rest.to_vec();
+        let then = move |first: Stmt| {
+            let mut stmts = vec![first];
+            stmts.extend(rest_.iter().cloned());
+            Term::Seq(stmts)
+        };
+        match first {
+            Stmt::Term(inner) if rest.is_empty() => Ok(Some(Step::new(term, inner.clone()))),
+            Stmt::Term(inner) => {
+                if let Some(step) = self.sub(term, inner, move |inner| then(Stmt::Term(inner)))? {
+                    return Ok(Some(step));
+                }
+                if self.pure && !inner.is_value() {
+                    return Ok(None);
+                }
+
+                Ok(Some(Step::new(term, Term::Seq(rest.to_vec()))))
+            }
+            Stmt::Let(var, inner) => {
+                if self.order == Order::ByValue && !self.pure {
+                    let var = var.clone();
+                    let step = self.sub(term, inner, move |inner| {
+                        then(Stmt::Let(var.clone(), inner))
+                    })?;
+                    if step.is_some() {
+                        return Ok(step);
+                    }
+                }
+
+                // the value of a block ending with a let is the bound value
+                if rest.is_empty() {
+                    return Ok(Some(Step::new(term, inner.clone())));
+                }
+                Ok(Some(Step::new(
+                    term,
+                    Term::Seq(rest.to_vec()).subst(var, inner),
+                )))
+            }
+            _ if self.pure => Ok(None),
+            _ => {
+                let val = match Term::Seq(vec![first.clone()]).eval(self.val_ctx) {
+                    Ok(val) => val,
+                    Err(ValError::Raise(exn)) => {
+                        return Ok(Some(Step::new(term, Term::raise(self.quote(&exn)?))));
+                    }
+                    Err(why) => return Err(why),
+                };
+
+                match rest {
+                    [] => Ok(Some(Step::new(term, self.quote(&val)?))),
+                    _ => Ok(Some(Step::new(term, Term::Seq(rest.to_vec())))),
+                }
+            }
+        }
+    }
+
+    // evaluates the term as a single step
+    fn atomic(&mut self, term: &Term) -> ValResult<Option<Step>> {
+        if self.pure {
+            return Ok(None);
+        }
+
+        match term.eval(self.val_ctx) {
+            Ok(val) => Ok(Some(Step::new(term, self.quote(&val)?))),
+            Err(ValError::Raise(exn)) => Ok(Some(Step::new(term, Term::raise(self.quote(&exn)?)))),
+            Err(why) => Err(why),
+        }
+    }
+
+    // the value as a term
+    fn quote(&mut self, val: &Val) -> ValResult<Term> {
+        let term = match val {
+            Val::Abs {
+                val_ctx,
+                param,
+                body,
+                ..
+            } => {
+                // the variables it captured, unless they're the same globally
+                let mut body = (**body).clone();
+                for name in body.free_vars() {
+                    let captured = match val_ctx.get(&name) {
+                        Some(captured) if name != *param => captured,
+                        _ => continue,
+                    };
+                    if self
+                        .val_ctx
+                        .get(&name)
+                        .is_some_and(|val| same(val, captured))
+                    {
+                        continue;
+                    }
+
+                    let captured = self.quote(captured)?;
+                    body = body.subst(&name, &captured);
+                }
+
+                Term::abs_untyped(param, body)
+            }
+            Val::Thunk(_) => self.quote(&val.force()?)?,
+
+            Val::Bool(true) => Term::var("True"),
+            Val::Bool(false) => Term::var("False"),
+            Val::Int(i) => Term::Int(*i),
+            Val::Unit => Term::var("Unit"),
+
+            Val::List(list) => Term::List(
+                list.iter()
+                    .map(|val| self.quote(val))
+                    .collect::<ValResult<_>>()?,
+            ),
+            Val::Pair(pair) => Term::pair(self.quote(&pair.0)?, self.quote(&pair.1)?),
+            Val::Con(name, args) => {
+                let mut con = Term::var(name);
+                for arg in args {
+                    con = Term::app(con, self.quote(arg)?);
+                }
+                con
+            }
+
+            Val::Op(op) => Term::var(op),
+            _ => Term::var(&self.name(val)),
+        };
+
+        Ok(term)
+    }
+
+    // the name the value is bound to, binding it to a fresh one if needed
+    fn name(&mut self, val: &Val) -> String {
+        let kind = match val {
+            Val::Native(_) => "fun",
+            Val::Ref(_) => "ref",
+            Val::Array(_) => "array",
+            Val::Map(_) => "map",
+            Val::Set(_) => "set",
+            Val::Stream(_) => "stream",
+            _ => "k",
+        };
+
+        for i in 1.. {
+            let name = format!("{}#{}", kind, i);
+            match self.val_ctx.get(&name) {
+                Some(bound) if same(bound, val) => return name,
+                Some(_) => continue,
+                None => {
+                    *self.val_ctx = self.val_ctx.insert(name.clone(), val.clone());
+                    return name;
+                }
+            }
+        }
+
+        unreachable!()
+    }
+}
+
+// whether the values are the very same one, rather than equal
+pub(crate) fn same(a: &Val, b: &Val) -> bool {
+    match (a, b) {
+        (Val::Abs { body: a, .. }, Val::Abs { body: b, .. }) => Rc::ptr_eq(a, b),
+        (Val::Native(a), Val::Native(b)) => Rc::ptr_eq(a, b),
+        (Val::Ref(a), Val::Ref(b)) => Rc::ptr_eq(a, b),
+        (Val::Array(a), Val::Array(b)) => Rc::ptr_eq(a, b),
+        (Val::Op(a), Val::Op(b)) => a == b,
+        _ => false,
+    }
+}
+
+// whether the term returns from the function it's the body of
+fn returns(term: &Term) -> bool {
+    match term {
+        Term::Var { .. } | Term::Abs { .. } | Term::Int(_) | Term::Hole(_) => false,
+        Term::App { func: a, arg: b }
+        | Term::Compose {
+            first: a,
+            second: b,
+        }
+        | Term::Cons { head: a, tail: b }
+        | Term::Pair { fst: a, snd: b }
+        | Term::Assign { target: a, val: b } => returns(a) || returns(b),
+        Term::List(items) => items.iter().any(returns),
+        Term::Ascribe { term, .. } | Term::Ref(term) | Term::Deref(term) | Term::Raise(term) => {
+            returns(term)
+        }
+        Term::If {
+            cond,
+            t_true,
+            t_false,
+        } => returns(cond) || returns(t_true) || returns(t_false),
+        Term::Match { scrutinee, arms } => {
+            returns(scrutinee) || arms.iter().any(|(_, body)| returns(body))
+        }
+        Term::Seq(stmts) => stmts.iter().any(stmt_returns),
+        Term::Try { body, handler, .. } => returns(body) || returns(handler),
+        Term::Perform { arg, .. } => returns(arg),
+        Term::Handle { body, handler } => {
+            returns(body)
+                || handler.ret.as_ref().is_some_and(|(_, term)| returns(term))
+                || handler.ops.iter().any(|clause| returns(&clause.body))
+        }
+    }
+}
+
+fn stmt_returns(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Term(term) | Stmt::Let(_, term) => returns(term),
+        Stmt::While { cond, body } => returns(cond) || body.iter().any(stmt_returns),
+        Stmt::For { from, to, body, .. } => {
+            returns(from) || returns(to) || body.iter().any(stmt_returns)
+        }
+        Stmt::Break | Stmt::Continue => false,
+        Stmt::Return(_) => true,
+    }
+}
+
+impl Pat {
+    // matches the term against the pattern, adding the variables it binds
+    // to binds; None if the term isn't known well enough yet, e.g. a
+    // variable
+    fn matches(&self, term: &Term, binds: &mut Vec<(String, Term)>) -> Option<bool> {
+        match (self, term) {
+            (_, Term::Ascribe { term, .. }) => self.matches(term, binds),
+            (Pat::Wild, _) => Some(true),
+            (Pat::Var(name), _) => {
+                binds.push((name.clone(), term.clone()));
+                Some(true)
+            }
+            (Pat::Int(i), Term::Int(j)) => Some(i == j),
+            (Pat::Con(name, pats), _) if term.is_con() => {
+                let mut args = vec![];
+                let mut head = term;
+                while let Term::App { func, arg } = head {
+                    args.push(&**arg);
+                    head = func;
+                }
+                args.reverse();
+
+                match head {
+                    Term::Var { name: con } if con == name && args.len() == pats.len() => {
+                        matches_all(pats.iter().zip(args), binds)
+                    }
+                    _ => Some(false),
+                }
+            }
+            (Pat::Nil, Term::List(items)) => Some(items.is_empty()),
+            (Pat::Nil, Term::Cons { .. }) => Some(false),
+            (Pat::Cons(head, tail), Term::List(items)) => match items.split_first() {
+                None => Some(false),
+                Some((x, xs)) => {
+                    let xs = Term::List(xs.to_vec());
+                    matches_all([(&**head, x), (&**tail, &xs)].into_iter(), binds)
+                }
+            },
+            (Pat::Cons(head, tail), Term::Cons { head: x, tail: xs }) => {
+                matches_all([(&**head, &**x), (&**tail, &**xs)].into_iter(), binds)
+            }
+            (Pat::Pair(fst, snd), Term::Pair { fst: x, snd: y }) => {
+                matches_all([(&**fst, &**x), (&**snd, &**y)].into_iter(), binds)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn matches_all<'a, I: Iterator<Item = (&'a Pat, &'a Term)>>(
+    pairs: I,
+    binds: &mut Vec<(String, Term)>,
+) -> Option<bool> {
+    for (pat, term) in pairs {
+        if !pat.matches(term, binds)? {
+            return Some(false);
+        }
+    }
+
+    Some(true)
+}
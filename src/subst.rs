@@ -0,0 +1,793 @@
+use crate::terms::Handler;
+use crate::terms::OpClause;
+use crate::terms::Pat;
+use crate::terms::Stmt;
+use crate::terms::Term;
+
+use std::collections::BTreeSet;
+
+// substitution
+//
+// terms are substituted into without capturing their free variables, the
+// binders in the way being renamed to fresh variables instead. terms equal
+// up to the names of their bound variables are alpha equivalent
+
+impl Term {
+    // variables occurring in the term outside the scope of any binder for
+    // them
+    pub fn free_vars(&self) -> BTreeSet<String> {
+        let mut vars = BTreeSet::new();
+        collect_free_vars(self, &mut vars);
+        vars
+    }
+
+    // the term with by in place of the free occurrences of name, renaming
+    // the binders which would capture the free variables of by
+    pub fn subst(&self, name: &str, by: &Term) -> Term {
+        Subst::new(name, by).term(self)
+    }
+
+    // the term with the variables bound to the terms at once
+    pub fn subst_all(&self, binds: &[(String, Term)]) -> Term {
+        let mut avoid = self.free_vars();
+        for (name, by) in binds {
+            avoid.insert(name.clone());
+            avoid.extend(by.free_vars());
+        }
+
+        // renamed first, so that no term substitutes into another
+        let mut term = self.clone();
+        let mut renamed = vec![];
+        for (name, by) in binds {
+            let tmp = fresh(name, &avoid);
+            avoid.insert(tmp.clone());
+            term = term.subst(name, &Term::var(&tmp));
+            renamed.push((tmp, by));
+        }
+        for (tmp, by) in renamed {
+            term = term.subst(&tmp, by);
+        }
+
+        term
+    }
+
+    // whether the terms are the same up to the names of bound variables
+    pub fn alpha_eq(&self, other: &Term) -> bool {
+        AlphaEq::default().term(self, other)
+    }
+}
+
+fn collect_free_vars(term: &Term, vars: &mut BTreeSet<String>) {
+    // the free variables of a term under binders
+    fn under(bound: &[String], term: &Term, vars: &mut BTreeSet<String>) {
+        vars.extend(
+            term.free_vars()
+                .into_iter()
+                .filter(|var| !bound.contains(var)),
+        );
+    }
+
+    match term {
+        Term::Var { name } => {
+            vars.insert(name.clone());
+        }
+        Term::Abs {
+            param_name, body, ..
+        } => under(std::slice::from_ref(param_name), body, vars),
+        Term::App { func: a, arg: b }
+        | Term::Compose {
+            first: a,
+            second: b,
+        }
+        | Term::Cons { head: a, tail: b }
+        | Term::Pair { fst: a, snd: b }
+        | Term::Assign { target: a, val: b } => {
+            collect_free_vars(a, vars);
+            collect_free_vars(b, vars);
+        }
+        Term::Int(_) | Term::Hole(_) => {}
+        Term::List(items) => items.iter().for_each(|item| collect_free_vars(item, vars)),
+        Term::Ascribe { term, .. } | Term::Ref(term) | Term::Deref(term) | Term::Raise(term) => {
+            collect_free_vars(term, vars)
+        }
+        Term::If {
+            cond,
+            t_true,
+            t_false,
+        } => {
+            collect_free_vars(cond, vars);
+            collect_free_vars(t_true, vars);
+            collect_free_vars(t_false, vars);
+        }
+        Term::Match { scrutinee, arms } => {
+            collect_free_vars(scrutinee, vars);
+            for (pat, body) in arms {
+                under(&pat.vars(), body, vars);
+            }
+        }
+        Term::Seq(stmts) => vars.extend(block_free_vars(stmts)),
+        Term::Try { body, pat, handler } => {
+            collect_free_vars(body, vars);
+            under(&pat.vars(), handler, vars);
+        }
+        Term::Perform { arg, .. } => collect_free_vars(arg, vars),
+        Term::Handle { body, handler } => {
+            collect_free_vars(body, vars);
+            if let Some((x, term)) = &handler.ret {
+                under(std::slice::from_ref(x), term, vars);
+            }
+            for clause in &handler.ops {
+                under(
+                    &[clause.param.clone(), clause.k.clone()],
+                    &clause.body,
+                    vars,
+                );
+            }
+        }
+    }
+}
+
+fn block_free_vars(stmts: &[Stmt]) -> BTreeSet<String> {
+    let mut vars = BTreeSet::new();
+    for stmt in stmts.iter().rev() {
+        match stmt {
+            Stmt::Term(term) | Stmt::Return(term) => collect_free_vars(term, &mut vars),
+            Stmt::Let(var, term) => {
+                vars.remove(var);
+                collect_free_vars(term, &mut vars);
+            }
+            Stmt::While { cond, body } => {
+                collect_free_vars(cond, &mut vars);
+                vars.extend(block_free_vars(body));
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                collect_free_vars(from, &mut vars);
+                collect_free_vars(to, &mut vars);
+                vars.extend(block_free_vars(body).into_iter().filter(|v| v != var));
+            }
+            Stmt::Break | Stmt::Continue => {}
+        }
+    }
+
+    vars
+}
+
+// a variable named after base which isn't in avoid
+pub fn fresh(base: &str, avoid: &BTreeSet<String>) -> String {
+    (1..)
+        .map(|i| format!("{}{}", base, i))
+        .find(|name| !avoid.contains(name))
+        .unwrap()
+}
+
+fn subst_block(stmts: &[Stmt], name: &str, by: &Term) -> Vec<Stmt> {
+    Subst::new(name, by).block(stmts)
+}
+
+struct Subst<'a> {
+    name: &'a str,
+    by: &'a Term,
+    // free variables of by, which mustn't be captured
+    free: BTreeSet<String>,
+}
+
+impl<'a> Subst<'a> {
+    fn new(name: &'a str, by: &'a Term) -> Self {
+        Subst {
+            name,
+            by,
+            free: by.free_vars(),
+        }
+    }
+
+    fn term(&self, term: &Term) -> Term {
+        let sub = |term: &Term| Box::new(self.term(term));
+
+        match term {
+            Term::Var { name } if name == self.name => self.by.clone(),
+            Term::Var { .. } | Term::Int(_) | Term::Hole(_) => term.clone(),
+            Term::Abs {
+                param_name,
+                param_typ,
+                body,
+            } => {
+                let (vars, body) = self.binders(std::slice::from_ref(param_name), body);
+                Term::Abs {
+                    param_name: vars[0].clone(),
+                    param_typ: param_typ.clone(),
+                    body: Box::new(body),
+                }
+            }
+            Term::App { func, arg } => Term::App {
+                func: sub(func),
+                arg: sub(arg),
+            },
+            Term::Compose { first, second } => Term::Compose {
+                first: sub(first),
+                second: sub(second),
+            },
+
+            Term::List(items) => Term::List(items.iter().map(|item| self.term(item)).collect()),
+            Term::Cons { head, tail } => Term::Cons {
+                head: sub(head),
+                tail: sub(tail),
+            },
+            Term::Pair { fst, snd } => Term::Pair {
+                fst: sub(fst),
+                snd: sub(snd),
+            },
+
+            Term::Ascribe { term, typ } => Term::Ascribe {
+                term: sub(term),
+                typ: typ.clone(),
+            },
+
+            Term::Ref(term) => Term::Ref(sub(term)),
+            Term::Deref(term) => Term::Deref(sub(term)),
+            Term::Assign { target, val } => Term::Assign {
+                target: sub(target),
+                val: sub(val),
+            },
+
+            Term::If {
+                cond,
+                t_true,
+                t_false,
+            } => Term::If {
+                cond: sub(cond),
+                t_true: sub(t_true),
+                t_false: sub(t_false),
+            },
+            Term::Match { scrutinee, arms } => Term::Match {
+                scrutinee: sub(scrutinee),
+                arms: arms.iter().map(|(pat, body)| self.arm(pat, body)).collect(),
+            },
+            Term::Seq(stmts) => Term::Seq(self.block(stmts)),
+
+            Term::Raise(exn) => Term::Raise(sub(exn)),
+            Term::Try { body, pat, handler } => {
+                let (pat, handler) = self.arm(pat, handler);
+                Term::Try {
+                    body: sub(body),
+                    pat,
+                    handler: Box::new(handler),
+                }
+            }
+
+            Term::Perform { op, arg } => Term::Perform {
+                op: op.clone(),
+                arg: sub(arg),
+            },
+            Term::Handle { body, handler } => {
+                let ret = handler.ret.as_ref().map(|(x, term)| {
+                    let (vars, term) = self.binders(std::slice::from_ref(x), term);
+                    (vars[0].clone(), Box::new(term))
+                });
+                let ops = handler
+                    .ops
+                    .iter()
+                    .map(|clause| {
+                        let (vars, body) =
+                            self.binders(&[clause.param.clone(), clause.k.clone()], &clause.body);
+                        OpClause::new(&clause.op, &vars[0], &vars[1], body)
+                    })
+                    .collect();
+
+                Term::Handle {
+                    body: sub(body),
+                    handler: Handler { ret, ops },
+                }
+            }
+        }
+    }
+
+    // substitutes into the body of binders of vars, renaming those which
+    // would capture a free variable of by
+    fn binders(&self, vars: &[String], body: &Term) -> (Vec<String>, Term) {
+        if vars.iter().any(|var| var == self.name) {
+            return (vars.to_vec(), body.clone());
+        }
+
+        let mut avoid = body.free_vars();
+        avoid.extend(self.free.iter().cloned());
+        avoid.insert(self.name.to_string());
+
+        let mut body = body.clone();
+        let mut renamed = vec![];
+        for var in vars {
+            if !self.free.contains(var) {
+                renamed.push(var.clone());
+                continue;
+            }
+
+            let var_ = fresh(var, &avoid);
+            avoid.insert(var_.clone());
+            body = body.subst(var, &Term::var(&var_));
+            renamed.push(var_);
+        }
+
+        (renamed, self.term(&body))
+    }
+
+    fn arm(&self, pat: &Pat, body: &Term) -> (Pat, Term) {
+        let vars = pat.vars();
+        let (renamed, body) = self.binders(&vars, body);
+
+        let pat = vars
+            .iter()
+            .zip(&renamed)
+            .fold(pat.clone(), |pat, (var, var_)| pat.rename(var, var_));
+        (pat, body)
+    }
+
+    fn block(&self, stmts: &[Stmt]) -> Vec<Stmt> {
+        let (first, rest) = match stmts.split_first() {
+            None => return vec![],
+            Some(split) => split,
+        };
+
+        let (first, rest) = match first {
+            Stmt::Let(var, term) => {
+                let term = self.term(term);
+                if var == self.name {
+                    (Stmt::Let(var.clone(), term), rest.to_vec())
+                } else if self.free.contains(var) {
+                    let mut avoid = block_free_vars(rest);
+                    avoid.extend(self.free.iter().cloned());
+                    avoid.insert(self.name.to_string());
+
+                    let var_ = fresh(var, &avoid);
+                    let rest = subst_block(rest, var, &Term::var(&var_));
+                    (Stmt::Let(var_, term), self.block(&rest))
+                } else {
+                    (Stmt::Let(var.clone(), term), self.block(rest))
+                }
+            }
+            Stmt::Term(term) => (Stmt::Term(self.term(term)), self.block(rest)),
+            Stmt::While { cond, body } => (
+                Stmt::While {
+                    cond: self.term(cond),
+                    body: self.block(body),
+                },
+                self.block(rest),
+            ),
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                let (var, body) = if var == self.name {
+                    (var.clone(), body.clone())
+                } else if self.free.contains(var) {
+                    let mut avoid = block_free_vars(body);
+                    avoid.extend(self.free.iter().cloned());
+                    avoid.insert(self.name.to_string());
+
+                    let var_ = fresh(var, &avoid);
+                    let body = subst_block(body, var, &Term::var(&var_));
+                    (var_, self.block(&body))
+                } else {
+                    (var.clone(), self.block(body))
+                };
+
+                (
+                    Stmt::For {
+                        var,
+                        from: self.term(from),
+                        to: self.term(to),
+                        body,
+                    },
+                    self.block(rest),
+                )
+            }
+            Stmt::Return(term) => (Stmt::Return(self.term(term)), self.block(rest)),
+            Stmt::Break | Stmt::Continue => (first.clone(), self.block(rest)),
+        };
+
+        std::iter::once(first).chain(rest).collect()
+    }
+}
+
+// pairs of variables bound at the same place on either side, innermost
+// last
+#[derive(Default)]
+struct AlphaEq {
+    bound: Vec<(String, String)>,
+}
+
+impl AlphaEq {
+    fn var(&self, a: &str, b: &str) -> bool {
+        let left = self.bound.iter().rposition(|(x, _)| x == a);
+        let right = self.bound.iter().rposition(|(_, y)| y == b);
+
+        match (left, right) {
+            (None, None) => a == b,
+            (left, right) => left == right,
+        }
+    }
+
+    // compares the terms under the pairs of binders
+    fn under(&mut self, binders: Vec<(String, String)>, a: &Term, b: &Term) -> bool {
+        let len = self.bound.len();
+        self.bound.extend(binders);
+        let eq = self.term(a, b);
+        self.bound.truncate(len);
+
+        eq
+    }
+
+    fn term(&mut self, a: &Term, b: &Term) -> bool {
+        match (a, b) {
+            (Term::Var { name: a }, Term::Var { name: b }) => self.var(a, b),
+            (
+                Term::Abs {
+                    param_name: x,
+                    param_typ: t,
+                    body: a,
+                },
+                Term::Abs {
+                    param_name: y,
+                    param_typ: u,
+                    body: b,
+                },
+            ) => t == u && self.under(vec![(x.clone(), y.clone())], a, b),
+            (Term::App { func: f, arg: x }, Term::App { func: g, arg: y })
+            | (
+                Term::Compose {
+                    first: f,
+                    second: x,
+                },
+                Term::Compose {
+                    first: g,
+                    second: y,
+                },
+            )
+            | (Term::Cons { head: f, tail: x }, Term::Cons { head: g, tail: y })
+            | (Term::Pair { fst: f, snd: x }, Term::Pair { fst: g, snd: y })
+            | (Term::Assign { target: f, val: x }, Term::Assign { target: g, val: y }) => {
+                self.term(f, g) && self.term(x, y)
+            }
+
+            (Term::Int(i), Term::Int(j)) => i == j,
+            (Term::List(xs), Term::List(ys)) => {
+                xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.term(x, y))
+            }
+
+            (Term::Ascribe { term: a, typ: t }, Term::Ascribe { term: b, typ: u }) => {
+                t == u && self.term(a, b)
+            }
+            (Term::Hole(a), Term::Hole(b)) => a == b,
+
+            (Term::Ref(a), Term::Ref(b))
+            | (Term::Deref(a), Term::Deref(b))
+            | (Term::Raise(a), Term::Raise(b)) => self.term(a, b),
+
+            (
+                Term::If {
+                    cond: c,
+                    t_true: x,
+                    t_false: y,
+                },
+                Term::If {
+                    cond: d,
+                    t_true: z,
+                    t_false: w,
+                },
+            ) => self.term(c, d) && self.term(x, z) && self.term(y, w),
+            (
+                Term::Match {
+                    scrutinee: a,
+                    arms: xs,
+                },
+                Term::Match {
+                    scrutinee: b,
+                    arms: ys,
+                },
+            ) => {
+                self.term(a, b)
+                    && xs.len() == ys.len()
+                    && xs
+                        .iter()
+                        .zip(ys)
+                        .all(|((p, x), (q, y))| self.arm(p, x, q, y))
+            }
+            (Term::Seq(xs), Term::Seq(ys)) => self.block(xs, ys),
+
+            (
+                Term::Try {
+                    body: a,
+                    pat: p,
+                    handler: x,
+                },
+                Term::Try {
+                    body: b,
+                    pat: q,
+                    handler: y,
+                },
+            ) => self.term(a, b) && self.arm(p, x, q, y),
+
+            (Term::Perform { op: f, arg: a }, Term::Perform { op: g, arg: b }) => {
+                f == g && self.term(a, b)
+            }
+            (
+                Term::Handle {
+                    body: a,
+                    handler: h,
+                },
+                Term::Handle {
+                    body: b,
+                    handler: k,
+                },
+            ) => {
+                let ret = match (&h.ret, &k.ret) {
+                    (None, None) => true,
+                    (Some((x, a)), Some((y, b))) => self.under(vec![(x.clone(), y.clone())], a, b),
+                    _ => false,
+                };
+
+                self.term(a, b)
+                    && ret
+                    && h.ops.len() == k.ops.len()
+                    && h.ops.iter().zip(&k.ops).all(|(a, b)| {
+                        a.op == b.op
+                            && self.under(
+                                vec![
+                                    (a.param.clone(), b.param.clone()),
+                                    (a.k.clone(), b.k.clone()),
+                                ],
+                                &a.body,
+                                &b.body,
+                            )
+                    })
+            }
+
+            _ => false,
+        }
+    }
+
+    fn arm(&mut self, p: &Pat, a: &Term, q: &Pat, b: &Term) -> bool {
+        let mut binders = vec![];
+        pat_eq(p, q, &mut binders) && self.under(binders, a, b)
+    }
+
+    fn block(&mut self, xs: &[Stmt], ys: &[Stmt]) -> bool {
+        let ((x, xs), (y, ys)) = match (xs.split_first(), ys.split_first()) {
+            (None, None) => return true,
+            (Some(x), Some(y)) => (x, y),
+            _ => return false,
+        };
+
+        let len = self.bound.len();
+        let eq = match (x, y) {
+            (Stmt::Term(a), Stmt::Term(b)) | (Stmt::Return(a), Stmt::Return(b)) => self.term(a, b),
+            (Stmt::Let(x, a), Stmt::Let(y, b)) => {
+                let eq = self.term(a, b);
+                self.bound.push((x.clone(), y.clone()));
+                eq
+            }
+            (Stmt::While { cond: a, body: x }, Stmt::While { cond: b, body: y }) => {
+                self.term(a, b) && self.block(x, y)
+            }
+            (
+                Stmt::For {
+                    var: x,
+                    from: a,
+                    to: c,
+                    body: xs,
+                },
+                Stmt::For {
+                    var: y,
+                    from: b,
+                    to: d,
+                    body: ys,
+                },
+            ) => {
+                self.term(a, b) && self.term(c, d) && {
+                    self.bound.push((x.clone(), y.clone()));
+                    let eq = self.block(xs, ys);
+                    self.bound.pop();
+                    eq
+                }
+            }
+            (Stmt::Break, Stmt::Break) | (Stmt::Continue, Stmt::Continue) => true,
+            _ => false,
+        };
+        let eq = eq && self.block(xs, ys);
+        self.bound.truncate(len);
+
+        eq
+    }
+}
+
+// whether the patterns have the same shape, pairing the variables they bind
+fn pat_eq(p: &Pat, q: &Pat, binders: &mut Vec<(String, String)>) -> bool {
+    match (p, q) {
+        (Pat::Wild, Pat::Wild) | (Pat::Nil, Pat::Nil) => true,
+        (Pat::Var(x), Pat::Var(y)) => {
+            binders.push((x.clone(), y.clone()));
+            true
+        }
+        (Pat::Int(i), Pat::Int(j)) => i == j,
+        (Pat::Con(f, ps), Pat::Con(g, qs)) => {
+            f == g && ps.len() == qs.len() && ps.iter().zip(qs).all(|(p, q)| pat_eq(p, q, binders))
+        }
+        (Pat::Cons(p, ps), Pat::Cons(q, qs)) | (Pat::Pair(p, ps), Pat::Pair(q, qs)) => {
+            pat_eq(p, q, binders) && pat_eq(ps, qs, binders)
+        }
+        _ => false,
+    }
+}
+
+impl Pat {
+    // variables bound by the pattern
+    pub fn vars(&self) -> Vec<String> {
+        match self {
+            Pat::Wild | Pat::Int(_) | Pat::Nil => vec![],
+            Pat::Var(name) => vec![name.clone()],
+            Pat::Con(_, args) => args.iter().flat_map(Pat::vars).collect(),
+            Pat::Cons(a, b) | Pat::Pair(a, b) => a.vars().into_iter().chain(b.vars()).collect(),
+        }
+    }
+
+    // the pattern binding to in place of var
+    pub fn rename(&self, var: &str, to: &str) -> Pat {
+        match self {
+            Pat::Var(name) if name == var => Pat::Var(to.to_string()),
+            Pat::Wild | Pat::Var(_) | Pat::Int(_) | Pat::Nil => self.clone(),
+            Pat::Con(name, args) => Pat::Con(
+                name.clone(),
+                args.iter().map(|arg| arg.rename(var, to)).collect(),
+            ),
+            Pat::Cons(head, tail) => Pat::cons(head.rename(var, to), tail.rename(var, to)),
+            Pat::Pair(fst, snd) => Pat::pair(fst.rename(var, to), snd.rename(var, to)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, so that the terms are the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        // few names, so that binders often clash with free variables
+        fn name(&mut self) -> String {
+            ["x", "y", "z", "x1"][self.below(4) as usize].to_string()
+        }
+
+        fn pat(&mut self, depth: u32) -> Pat {
+            match self.below(if depth == 0 { 2 } else { 4 }) {
+                0 => Pat::Wild,
+                1 => Pat::Var(self.name()),
+                2 => Pat::pair(self.pat(depth - 1), self.pat(depth - 1)),
+                _ => Pat::con("Some", vec![self.pat(depth - 1)]),
+            }
+        }
+
+        fn term(&mut self, depth: u32) -> Term {
+            if depth == 0 {
+                return match self.below(4) {
+                    0 => Term::Int(self.below(3) as i32),
+                    _ => Term::var(&self.name()),
+                };
+            }
+
+            let d = depth - 1;
+            match self.below(9) {
+                0 => Term::var(&self.name()),
+                1 => Term::abs_untyped(&self.name(), self.term(d)),
+                2 => Term::app(self.term(d), self.term(d)),
+                3 => Term::pair(self.term(d), self.term(d)),
+                4 => {
+                    let arms = (0..2).map(|_| (self.pat(2), self.term(d))).collect();
+                    Term::match_(self.term(d), arms)
+                }
+                5 => Term::Try {
+                    body: Box::new(self.term(d)),
+                    pat: self.pat(2),
+                    handler: Box::new(self.term(d)),
+                },
+                6 => Term::Seq(vec![
+                    Stmt::Let(self.name(), self.term(d)),
+                    Stmt::Term(self.term(d)),
+                ]),
+                7 => Term::Seq(vec![
+                    Stmt::For {
+                        var: self.name(),
+                        from: self.term(d),
+                        to: self.term(d),
+                        body: vec![Stmt::Term(self.term(d))],
+                    },
+                    Stmt::Term(self.term(d)),
+                ]),
+                _ => Term::Int(self.below(3) as i32),
+            }
+        }
+    }
+
+    fn terms() -> impl Iterator<Item = Term> {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        (0..500).map(move |_| rng.term(4))
+    }
+
+    #[test]
+    fn alpha_eq_reflexive_and_symmetric() {
+        let ts: Vec<_> = terms().collect();
+        for (t, u) in ts.iter().zip(ts.iter().skip(1)) {
+            assert!(t.alpha_eq(t), "{}", t);
+            assert_eq!(t.alpha_eq(u), u.alpha_eq(t), "{} and {}", t, u);
+
+            // binding a fresh variable in place of x gives an equivalent term
+            let v = fresh("v", &t.free_vars());
+            let a = Term::abs_untyped("x", t.clone());
+            let b = Term::abs_untyped(&v, t.subst("x", &Term::var(&v)));
+            assert!(a.alpha_eq(&b) && b.alpha_eq(&a), "{} and {}", a, b);
+        }
+    }
+
+    #[test]
+    fn subst_free_vars() {
+        let mut rng = Rng(42);
+        for t in terms() {
+            let (x, s) = (rng.name(), rng.term(2));
+            let result = t.subst(&x, &s);
+
+            let mut allowed = t.free_vars();
+            allowed.remove(&x);
+            allowed.extend(s.free_vars());
+            assert!(
+                result.free_vars().is_subset(&allowed),
+                "{}[{} := {}] = {}",
+                t,
+                x,
+                s,
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn subst_avoids_capture() {
+        // the only names the terms bind are x, y, z and x1, so s always
+        // mentions names bound in t
+        let mut rng = Rng(7);
+        for t in terms() {
+            let x = rng.name();
+            let s = Term::pair(Term::var(&rng.name()), Term::var(&rng.name()));
+            let result = t.subst(&x, &s);
+
+            // where s is put in, its variables stay free
+            if t.free_vars().contains(&x) {
+                assert!(
+                    s.free_vars().is_subset(&result.free_vars()),
+                    "{}[{} := {}] = {}",
+                    t,
+                    x,
+                    s,
+                    result
+                );
+            } else {
+                assert!(result.alpha_eq(&t), "{}[{} := {}] = {}", t, x, s, result);
+            }
+        }
+
+        let t = Term::abs_untyped("y", Term::app(Term::var("x"), Term::var("y")));
+        let expected = Term::abs_untyped("z", Term::app(Term::var("y"), Term::var("z")));
+        assert!(t.subst("x", &Term::var("y")).alpha_eq(&expected));
+    }
+}
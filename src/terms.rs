@@ -7,28 +7,122 @@ pub enum Term {
     },
     Abs {
         param_name: String,
-        param_typ: Typ,
+        param_typ: Option<Typ>,
         body: Box<Self>,
     },
     App {
         func: Box<Self>,
         arg: Box<Self>,
     },
+    Compose {
+        first: Box<Self>,
+        second: Box<Self>,
+    },
 
     Int(i32),
 
+    List(Vec<Self>),
+    Cons {
+        head: Box<Self>,
+        tail: Box<Self>,
+    },
+    Pair {
+        fst: Box<Self>,
+        snd: Box<Self>,
+    },
+
+    Ascribe {
+        term: Box<Self>,
+        typ: Typ,
+    },
+    Hole(String),
+
+    // mutable references
+    Ref(Box<Self>),
+    Deref(Box<Self>),
+    Assign {
+        target: Box<Self>,
+        val: Box<Self>,
+    },
+
     If {
         cond: Box<Self>,
         t_true: Box<Self>,
         t_false: Box<Self>,
     },
+    Match {
+        scrutinee: Box<Self>,
+        arms: Vec<(Pat, Self)>,
+    },
     Seq(Vec<Stmt>),
+
+    // exceptions
+    Raise(Box<Self>),
+    Try {
+        body: Box<Self>,
+        pat: Pat,
+        handler: Box<Self>,
+    },
+
+    // effects
+    Perform {
+        op: String,
+        arg: Box<Self>,
+    },
+    Handle {
+        body: Box<Self>,
+        handler: Handler,
+    },
+}
+
+// clauses of handle ... with { ... }
+#[derive(Clone, Debug)]
+pub struct Handler {
+    // return x => body, applied to the value of the handled term
+    pub ret: Option<(String, Box<Term>)>,
+    pub ops: Vec<OpClause>,
+}
+
+// op x k => body, where k is the continuation of the operation
+#[derive(Clone, Debug)]
+pub struct OpClause {
+    pub op: String,
+    pub param: String,
+    pub k: String,
+    pub body: Term,
 }
 
 #[derive(Clone, Debug)]
 pub enum Stmt {
     Term(Term),
     Let(String, Term),
+
+    While {
+        cond: Term,
+        body: Vec<Stmt>,
+    },
+    For {
+        var: String,
+        from: Term,
+        to: Term,
+        body: Vec<Stmt>,
+    },
+    Break,
+    Continue,
+    // returns from the innermost function
+    Return(Term),
+}
+
+#[derive(Clone, Debug)]
+pub enum Pat {
+    Wild,
+    Var(String),
+    Int(i32),
+    // constructor applied to patterns, e.g. Some x, or a constant such as True
+    Con(String, Vec<Pat>),
+    Nil,
+    Cons(Box<Pat>, Box<Pat>),
+    Pair(Box<Pat>, Box<Pat>),
 }
 
 impl Term {
@@ -41,11 +135,31 @@ impl Term {
     pub fn abs(param_name: &str, param_typ: Typ, body: Self) -> Self {
         Term::Abs {
             param_name: param_name.to_string(),
-            param_typ,
+            param_typ: Some(param_typ),
             body: Box::new(body),
         }
     }
 
+    // fun x => body, only accepted where the type of x can be inferred
+    pub fn abs_untyped(param_name: &str, body: Self) -> Self {
+        Term::Abs {
+            param_name: param_name.to_string(),
+            param_typ: None,
+            body: Box::new(body),
+        }
+    }
+
+    // fun (x1: T1) ... (xn: Tn) => body
+    pub fn abs_n(params: Vec<(&str, Option<Typ>)>, body: Self) -> Self {
+        params
+            .into_iter()
+            .rev()
+            .fold(body, |body, (param_name, param_typ)| match param_typ {
+                Some(param_typ) => Term::abs(param_name, param_typ, body),
+                None => Term::abs_untyped(param_name, body),
+            })
+    }
+
     pub fn app(func: Self, arg: Self) -> Self {
         Term::App {
             func: Box::new(func),
@@ -53,6 +167,56 @@ impl Term {
         }
     }
 
+    pub fn compose(first: Self, second: Self) -> Self {
+        Term::Compose {
+            first: Box::new(first),
+            second: Box::new(second),
+        }
+    }
+
+    pub fn cons(head: Self, tail: Self) -> Self {
+        Term::Cons {
+            head: Box::new(head),
+            tail: Box::new(tail),
+        }
+    }
+
+    pub fn pair(fst: Self, snd: Self) -> Self {
+        Term::Pair {
+            fst: Box::new(fst),
+            snd: Box::new(snd),
+        }
+    }
+
+    pub fn match_(scrutinee: Self, arms: Vec<(Pat, Self)>) -> Self {
+        Term::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+        }
+    }
+
+    pub fn ascribe(term: Self, typ: Typ) -> Self {
+        Term::Ascribe {
+            term: Box::new(term),
+            typ,
+        }
+    }
+
+    pub fn ref_(term: Self) -> Self {
+        Term::Ref(Box::new(term))
+    }
+
+    pub fn deref(term: Self) -> Self {
+        Term::Deref(Box::new(term))
+    }
+
+    pub fn assign(target: Self, val: Self) -> Self {
+        Term::Assign {
+            target: Box::new(target),
+            val: Box::new(val),
+        }
+    }
+
     pub fn if_(cond: Self, t_true: Self, t_false: Self) -> Self {
         Term::If {
             cond: Box::new(cond),
@@ -60,6 +224,62 @@ impl Term {
             t_false: Box::new(t_false),
         }
     }
+
+    pub fn raise(exn: Self) -> Self {
+        Term::Raise(Box::new(exn))
+    }
+
+    // try body catch pat => handler, exceptions not matching pat are raised
+    // again
+    pub fn try_(body: Self, pat: Pat, handler: Self) -> Self {
+        Term::Try {
+            body: Box::new(body),
+            pat,
+            handler: Box::new(handler),
+        }
+    }
+
+    pub fn perform(op: &str, arg: Self) -> Self {
+        Term::Perform {
+            op: op.to_string(),
+            arg: Box::new(arg),
+        }
+    }
+
+    pub fn handle(body: Self, ret: Option<(&str, Self)>, ops: Vec<OpClause>) -> Self {
+        Term::Handle {
+            body: Box::new(body),
+            handler: Handler {
+                ret: ret.map(|(x, term)| (x.to_string(), Box::new(term))),
+                ops,
+            },
+        }
+    }
+
+    // whether the term is a constructor (by convention, named in uppercase)
+    // possibly applied to some arguments
+    pub(crate) fn is_con(&self) -> bool {
+        match self {
+            Term::Var { name } => name.starts_with(|c: char| c.is_ascii_uppercase()),
+            Term::App { func, .. } => func.is_con(),
+            _ => false,
+        }
+    }
+
+    // whether the term is a syntactic value, i.e. evaluating it can't have
+    // any effect
+    pub fn is_value(&self) -> bool {
+        match self {
+            Term::Var { .. } | Term::Abs { .. } | Term::Int(_) => true,
+            Term::List(items) => items.iter().all(Term::is_value),
+            Term::Cons { head, tail } => head.is_value() && tail.is_value(),
+            Term::Pair { fst, snd } => fst.is_value() && snd.is_value(),
+            Term::Ascribe { term, .. } => term.is_value(),
+            // constructor application, e.g. Some x
+            Term::App { func, arg } => func.is_value() && arg.is_value() && func.is_con(),
+            _ => false,
+        }
+    }
 }
 
 #[macro_export]
@@ -114,28 +334,87 @@ impl std::fmt::Display for Term {
                 param_name,
                 param_typ,
                 body,
-            } => write!(f, "(fun {}:{} => {})", param_name, param_typ, body),
+            } => match param_typ {
+                Some(param_typ) => write!(f, "(fun {}:{} => {})", param_name, param_typ, body),
+                None => write!(f, "(fun {} => {})", param_name, body),
+            },
             Term::App { func, arg } => write!(f, "({} {})", func, arg),
+            Term::Compose { first, second } => write!(f, "({} >> {})", first, second),
 
             Term::Int(i) => write!(f, "{}", *i),
 
+            Term::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Term::Cons { head, tail } => write!(f, "({} :: {})", head, tail),
+            Term::Pair { fst, snd } => write!(f, "({}, {})", fst, snd),
+
+            Term::Ascribe { term, typ } => write!(f, "({} : {})", term, typ),
+            Term::Hole(name) => write!(f, "?{}", name),
+
+            Term::Ref(term) => write!(f, "(ref {})", term),
+            Term::Deref(term) => write!(f, "!{}", term),
+            Term::Assign { target, val } => write!(f, "({} := {})", target, val),
+
             Term::If {
                 cond,
                 t_true,
                 t_false,
             } => write!(f, "(if {} then {} else {})", cond, t_true, t_false),
-            Term::Seq(stmts) => {
-                write!(f, "{{")?;
-                for (i, stmt) in stmts.iter().enumerate() {
+            Term::Match { scrutinee, arms } => {
+                write!(f, "(match {} with {{", scrutinee)?;
+                for (i, (pat, term)) in arms.iter().enumerate() {
                     if i != 0 {
-                        write!(f, ", ")?;
+                        write!(f, ",")?;
                     }
-                    write!(f, "{}", stmt)?;
+                    write!(f, " {} => {}", pat, term)?;
                 }
-                write!(f, "}}")
-            },
+                write!(f, " }})")
+            }
+            Term::Seq(stmts) => write_block(f, stmts),
+
+            Term::Raise(exn) => write!(f, "(raise {})", exn),
+            Term::Try { body, pat, handler } => {
+                write!(f, "(try {} catch {} => {})", body, pat, handler)
+            }
+
+            Term::Perform { op, arg } => write!(f, "(perform {} {})", op, arg),
+            Term::Handle { body, handler } => {
+                write!(f, "(handle {} with {{", body)?;
+                if let Some((x, term)) = &handler.ret {
+                    write!(f, " return {} => {}", x, term)?;
+                    if !handler.ops.is_empty() {
+                        write!(f, ",")?;
+                    }
+                }
+                for (i, clause) in handler.ops.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, " {}", clause)?;
+                }
+                write!(f, " }})")
+            }
+        }
+    }
+}
+
+fn write_block(f: &mut std::fmt::Formatter<'_>, stmts: &[Stmt]) -> std::fmt::Result {
+    write!(f, "{{")?;
+    for (i, stmt) in stmts.iter().enumerate() {
+        if i != 0 {
+            write!(f, ", ")?;
         }
+        write!(f, "{}", stmt)?;
     }
+    write!(f, "}}")
 }
 
 impl std::fmt::Display for Stmt {
@@ -143,6 +422,83 @@ impl std::fmt::Display for Stmt {
         match self {
             Stmt::Term(term) => write!(f, "{}", term),
             Stmt::Let(var, term) => write!(f, "let {} = {}", var, term),
+
+            Stmt::While { cond, body } => {
+                write!(f, "while {} do ", cond)?;
+                write_block(f, body)
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                write!(f, "for {} in {}..{} do ", var, from, to)?;
+                write_block(f, body)
+            }
+            Stmt::Break => write!(f, "break"),
+            Stmt::Continue => write!(f, "continue"),
+            Stmt::Return(term) => write!(f, "return {}", term),
+        }
+    }
+}
+
+impl OpClause {
+    pub fn new(op: &str, param: &str, k: &str, body: Term) -> Self {
+        OpClause {
+            op: op.to_string(),
+            param: param.to_string(),
+            k: k.to_string(),
+            body,
+        }
+    }
+}
+
+impl std::fmt::Display for OpClause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {} => {}", self.op, self.param, self.k, self.body)
+    }
+}
+
+impl Pat {
+    pub fn con(name: &str, args: Vec<Pat>) -> Self {
+        Pat::Con(name.to_string(), args)
+    }
+
+    pub fn cons(head: Pat, tail: Pat) -> Self {
+        Pat::Cons(Box::new(head), Box::new(tail))
+    }
+
+    pub fn pair(fst: Pat, snd: Pat) -> Self {
+        Pat::Pair(Box::new(fst), Box::new(snd))
+    }
+
+    // [p1, ..., pn]
+    pub fn list(items: Vec<Pat>) -> Self {
+        items
+            .into_iter()
+            .rev()
+            .fold(Pat::Nil, |tail, head| Pat::cons(head, tail))
+    }
+}
+
+impl std::fmt::Display for Pat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::Var(name) => write!(f, "{}", name),
+            Pat::Int(i) => write!(f, "{}", i),
+            Pat::Con(name, args) if args.is_empty() => write!(f, "{}", name),
+            Pat::Con(name, args) => {
+                write!(f, "({}", name)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Pat::Nil => write!(f, "[]"),
+            Pat::Cons(head, tail) => write!(f, "({} :: {})", head, tail),
+            Pat::Pair(fst, snd) => write!(f, "({}, {})", fst, snd),
         }
     }
 }
@@ -3,7 +3,43 @@ use crate::terms::*;
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Typ {
     Atom(String),
-    Func { from: Box<Typ>, to: Box<Typ> },
+    // function type, along with the row of effects it may perform when
+    // applied
+    Func {
+        from: Box<Typ>,
+        to: Box<Typ>,
+        eff: Box<Typ>,
+    },
+    // applied type constructor, e.g. List Int
+    Con { name: String, args: Vec<Typ> },
+
+    // type variable, either bound by a forall or standing for an arbitrary
+    // type in the definition whose annotation mentions it
+    Var(String),
+    Forall { vars: Vec<String>, typ: Box<Typ> },
+
+    // effect row: the given effects, plus those of the tail if the row is
+    // open (the tail being a variable, or a unification variable while type
+    // checking)
+    Row {
+        effs: Vec<String>,
+        tail: Option<Box<Typ>>,
+    },
+    // an operation of an effect, as bound in the context; it's used as the
+    // function type param ->{effect} result
+    Op {
+        effect: String,
+        param: Box<Typ>,
+        result: Box<Typ>,
+    },
+
+    // unification variable, only ever seen while type checking
+    Meta(usize),
+
+    // type of a term whose type couldn't be determined (a hole or an
+    // ill-typed term), it is compatible with every other type so that
+    // checking can carry on past an error
+    Unknown,
 }
 
 impl Typ {
@@ -12,9 +48,179 @@ impl Typ {
     }
 
     pub fn func(from: Typ, to: Typ) -> Self {
+        Typ::func_eff(from, to, Typ::pure())
+    }
+
+    pub fn func_eff(from: Typ, to: Typ, eff: Typ) -> Self {
         Typ::Func {
             from: Box::new(from),
             to: Box::new(to),
+            eff: Box::new(eff),
+        }
+    }
+
+    // row of a function without effects
+    pub fn pure() -> Self {
+        Typ::Row {
+            effs: vec![],
+            tail: None,
+        }
+    }
+
+    pub fn row(effs: Vec<String>, tail: Option<Typ>) -> Self {
+        match tail {
+            Some(tail) if effs.is_empty() => tail,
+            tail => Typ::Row {
+                effs,
+                tail: tail.map(Box::new),
+            },
+        }
+    }
+
+    pub fn op(effect: &str, param: Typ, result: Typ) -> Self {
+        Typ::Op {
+            effect: effect.to_string(),
+            param: Box::new(param),
+            result: Box::new(result),
+        }
+    }
+
+    pub fn con(name: &str, args: Vec<Typ>) -> Self {
+        Typ::Con {
+            name: name.to_string(),
+            args,
+        }
+    }
+
+    // whether values of the type can be ordered, as Val::is_ordered; type
+    // variables are checked where they're instantiated
+    pub fn is_ordered(&self) -> bool {
+        match self {
+            Typ::Func { .. } | Typ::Op { .. } => false,
+            Typ::Con { name, args } => match name.as_str() {
+                "Ref" | "Array" | "Stream" => false,
+                // keys were already checked
+                "Map" => args[1].is_ordered(),
+                _ => args.iter().all(Typ::is_ordered),
+            },
+            Typ::Forall { typ, .. } => typ.is_ordered(),
+            _ => true,
+        }
+    }
+
+    pub fn list(elem: Typ) -> Self {
+        Typ::con("List", vec![elem])
+    }
+
+    pub fn pair(fst: Typ, snd: Typ) -> Self {
+        Typ::con("Pair", vec![fst, snd])
+    }
+
+    pub fn ref_(typ: Typ) -> Self {
+        Typ::con("Ref", vec![typ])
+    }
+
+    // type of exceptions, whose constructors are declared by the program
+    pub fn exn() -> Self {
+        Typ::atom("Exn")
+    }
+
+    // row of the built-in functions doing input or output; no handler can
+    // handle it, so it ends up in the type of every function calling them,
+    // up to main
+    pub fn io() -> Self {
+        Typ::row(vec!["IO".to_string()], None)
+    }
+
+    pub fn var(name: &str) -> Self {
+        Typ::Var(name.to_string())
+    }
+
+    pub fn forall(vars: &[&str], typ: Typ) -> Self {
+        Typ::Forall {
+            vars: vars.iter().map(|v| v.to_string()).collect(),
+            typ: Box::new(typ),
+        }
+    }
+
+    // replaces the given type variables
+    pub fn subst_vars(&self, sub: &crate::Map<String, Typ>) -> Typ {
+        match self {
+            Typ::Var(v) => sub.get(v).cloned().unwrap_or_else(|| self.clone()),
+            Typ::Func { from, to, eff } => {
+                Typ::func_eff(from.subst_vars(sub), to.subst_vars(sub), eff.subst_vars(sub))
+            }
+            Typ::Con { name, args } => Typ::Con {
+                name: name.clone(),
+                args: args.iter().map(|t| t.subst_vars(sub)).collect(),
+            },
+            Typ::Row { effs, tail } => {
+                Typ::row(effs.clone(), tail.as_ref().map(|t| t.subst_vars(sub)))
+            }
+            Typ::Op {
+                effect,
+                param,
+                result,
+            } => Typ::Op {
+                effect: effect.clone(),
+                param: Box::new(param.subst_vars(sub)),
+                result: Box::new(result.subst_vars(sub)),
+            },
+            Typ::Forall { vars, typ } => {
+                let sub = vars.iter().fold(sub.clone(), |sub, v| {
+                    if sub.contains_key(v) {
+                        sub.remove(v).unwrap().0
+                    } else {
+                        sub
+                    }
+                });
+
+                Typ::Forall {
+                    vars: vars.clone(),
+                    typ: Box::new(typ.subst_vars(&sub)),
+                }
+            }
+            Typ::Atom(_) | Typ::Meta(_) | Typ::Unknown => self.clone(),
+        }
+    }
+
+    // free type variables, in order of appearance
+    pub fn vars(&self) -> Vec<String> {
+        let mut vars = vec![];
+        self.collect_vars(&mut vars);
+        vars
+    }
+
+    fn collect_vars(&self, vars: &mut Vec<String>) {
+        match self {
+            Typ::Var(v) => {
+                if !vars.contains(v) {
+                    vars.push(v.clone());
+                }
+            }
+            Typ::Func { from, to, eff } => {
+                from.collect_vars(vars);
+                to.collect_vars(vars);
+                eff.collect_vars(vars);
+            }
+            Typ::Row { tail, .. } => {
+                if let Some(tail) = tail {
+                    tail.collect_vars(vars);
+                }
+            }
+            Typ::Op { param, result, .. } => {
+                param.collect_vars(vars);
+                result.collect_vars(vars);
+            }
+            Typ::Con { args, .. } => args.iter().for_each(|t| t.collect_vars(vars)),
+            Typ::Forall { vars: bound, typ } => {
+                for v in typ.vars() {
+                    if !bound.contains(&v) && !vars.contains(&v) {
+                        vars.push(v);
+                    }
+                }
+            }
+            Typ::Atom(_) | Typ::Meta(_) | Typ::Unknown => {}
         }
     }
 }
@@ -35,113 +241,1347 @@ impl std::fmt::Display for Typ {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Typ::Atom(s) => write!(f, "{}", s),
-            Typ::Func { from, to } => write!(f, "({} -> {})", from, to),
+            Typ::Func { from, to, eff } => match eff.as_ref() {
+                Typ::Row { effs, tail: None } if effs.is_empty() => {
+                    write!(f, "({} -> {})", from, to)
+                }
+                eff @ Typ::Row { .. } => write!(f, "({} ->{} {})", from, eff, to),
+                eff => write!(f, "({} ->{{{}}} {})", from, eff, to),
+            },
+            Typ::Row { effs, tail } => {
+                // nested rows, left by substituting a row variable, are shown
+                // as one
+                let mut effs = effs.clone();
+                let mut tail = tail.as_deref();
+                while let Some(Typ::Row {
+                    effs: more,
+                    tail: rest,
+                }) = tail
+                {
+                    effs.extend(more.iter().cloned());
+                    tail = rest.as_deref();
+                }
+
+                write!(f, "{{{}", effs.join(", "))?;
+                match tail {
+                    None => {}
+                    Some(tail) if effs.is_empty() => write!(f, "{}", tail)?,
+                    Some(tail) => write!(f, " | {}", tail)?,
+                }
+                write!(f, "}}")
+            }
+            Typ::Op {
+                effect,
+                param,
+                result,
+            } => write!(f, "({} ->{{{}}} {})", param, effect, result),
+            Typ::Con { name, args } if name == "Pair" && args.len() == 2 => {
+                write!(f, "({}, {})", args[0], args[1])
+            }
+            Typ::Con { name, args } => {
+                write!(f, "{}", name)?;
+                for arg in args {
+                    match arg {
+                        Typ::Con { name, args } if name != "Pair" && !args.is_empty() => {
+                            write!(f, " ({})", arg)?
+                        }
+                        _ => write!(f, " {}", arg)?,
+                    }
+                }
+                Ok(())
+            }
+            Typ::Var(v) => write!(f, "{}", v),
+            Typ::Forall { vars, typ } => write!(f, "forall {}. {}", vars.join(" "), typ),
+            Typ::Meta(_) => write!(f, "_"),
+            Typ::Unknown => write!(f, "?"),
         }
     }
 }
 
 // typing
 
+// row variable of the callbacks built-in functions take. A built-in calls
+// its callback itself, where no continuation can be captured, so the
+// callback may do IO but perform no operation a handler would resume; the
+// quote keeps programs from naming it
+pub const CALLBACK_ROW: &str = "r'";
+
 pub type TypContext = crate::Map<String, Typ>;
 
 #[derive(Clone, Debug)]
 pub enum TypError {
-    Undefined(String),
+    Undefined {
+        name: String,
+        similar: Vec<String>,
+        later: Vec<String>,
+    },
     Mismatch(Typ, Typ),
     Expected(String, Typ),
+    Unannotated(String),
+    UnexpectedAbs(Typ),
+    Ambiguous(Typ),
+    NotGeneralizable(Typ),
+    OutsideLoop(String),
+    OutsideFunc,
+    Unhandled(String),
+    NotAnOperation(String),
+    WrongEffect {
+        op: String,
+        effect: String,
+    },
+    MissingOperation {
+        effect: String,
+        op: String,
+    },
+    Arity {
+        con: String,
+        expected: usize,
+        found: usize,
+    },
+    Hole(Hole),
+    // map key or set element of a type without an ordering
+    Unordered(Typ),
+    // type variable in a declaration which can't be polymorphic
+    Unbound(String),
+    NotAnArrow {
+        effect: String,
+        op: String,
+        typ: Typ,
+    },
+    // effect performed by the callback of a built-in function
+    InCallback(String),
+    Many(Vec<TypError>),
 }
 
 pub type TypResult<T> = Result<T, TypError>;
 
+impl TypError {
+    // adds suggestions for undefined variables from names which aren't in
+    // scope yet, e.g. top-level definitions further down the program
+    pub fn declared_later(self, names: &[&str]) -> Self {
+        match self {
+            TypError::Undefined {
+                name,
+                similar,
+                mut later,
+            } => {
+                later.extend(suggest(&name, names.iter().copied()));
+                TypError::Undefined {
+                    name,
+                    similar,
+                    later,
+                }
+            }
+            TypError::Many(errs) => {
+                TypError::Many(errs.into_iter().map(|e| e.declared_later(names)).collect())
+            }
+            err => err,
+        }
+    }
+}
+
+// names close enough to the given one to probably be what was meant, best
+// matches first
+pub fn suggest<'a, I: Iterator<Item = &'a str>>(name: &str, names: I) -> Vec<String> {
+    let max = std::cmp::max(1, name.chars().count() / 3);
+
+    let mut close: Vec<_> = names
+        .map(|n| (edit_distance(name, n), n))
+        .filter(|(d, _)| *d <= max)
+        .collect();
+    close.sort();
+    close.dedup();
+
+    close.into_iter().take(3).map(|(_, n)| n.to_string()).collect()
+}
+
+// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut row = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            row.push((prev[j] + cost).min(prev[j + 1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+
+    prev[b.len()]
+}
+
 impl std::fmt::Display for TypError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            TypError::Undefined(var) => write!(f, "Variable \"{}\" isn't defined", var),
+            TypError::Undefined {
+                name,
+                similar,
+                later,
+            } => {
+                if later.contains(name) {
+                    return write!(
+                        f,
+                        "Variable \"{}\" isn't defined yet, it's only declared further down",
+                        name
+                    );
+                }
+
+                write!(f, "Variable \"{}\" isn't defined", name)?;
+
+                let suggestions: Vec<_> = similar
+                    .iter()
+                    .map(|s| format!("\"{}\"", s))
+                    .chain(later.iter().map(|s| format!("\"{}\" (declared further down)", s)))
+                    .collect();
+
+                match suggestions.split_last() {
+                    None => Ok(()),
+                    Some((last, [])) => write!(f, ", did you mean {}?", last),
+                    Some((last, rest)) => write!(f, ", did you mean {} or {}?", rest.join(", "), last),
+                }
+            }
             TypError::Mismatch(exp, rec) => {
                 write!(f, "Expected type \"{}\" but found \"{}\"", exp, rec)
             }
             TypError::Expected(exp, rec) => write!(f, "Expected {} but found \"{}\"", exp, rec),
+            TypError::Unannotated(param) => write!(
+                f,
+                "Can't infer the type of parameter \"{}\", consider annotating it",
+                param
+            ),
+            TypError::UnexpectedAbs(exp) => {
+                write!(f, "Expected type \"{}\" but found a function", exp)
+            }
+            TypError::Ambiguous(typ) => write!(
+                f,
+                "Can't fully infer the type \"{}\", consider annotating it",
+                typ
+            ),
+            TypError::NotGeneralizable(typ) => write!(
+                f,
+                "The type \"{}\" can't be polymorphic, as the definition isn't a value",
+                typ
+            ),
+            TypError::OutsideLoop(stmt) => {
+                write!(f, "\"{}\" can only be used inside a loop", stmt)
+            }
+            TypError::OutsideFunc => write!(f, "\"return\" can only be used inside a function"),
+            TypError::Unhandled(effect) => write!(
+                f,
+                "Effect \"{}\" is neither handled here nor allowed by the enclosing function's type",
+                effect
+            ),
+            TypError::NotAnOperation(name) => {
+                write!(f, "\"{}\" isn't an operation of any effect", name)
+            }
+            TypError::WrongEffect { op, effect } => write!(
+                f,
+                "Operation \"{}\" doesn't belong to effect \"{}\", handled by the other operations",
+                op, effect
+            ),
+            TypError::MissingOperation { effect, op } => write!(
+                f,
+                "Handler for effect \"{}\" is missing operation \"{}\"",
+                effect, op
+            ),
+            TypError::Arity {
+                con,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Constructor \"{}\" takes {} argument(s) but the pattern gives it {}",
+                con, expected, found
+            ),
+            TypError::Hole(hole) => write!(f, "{}", hole),
+            TypError::Unordered(typ) => write!(
+                f,
+                "Values of type {} can't be ordered, so they can't be map keys or set elements",
+                typ
+            ),
+            TypError::Unbound(var) => write!(
+                f,
+                "Type variable {} isn't bound, as exceptions and operations can't be polymorphic",
+                var
+            ),
+            TypError::NotAnArrow { effect, op, typ } => write!(
+                f,
+                "Operation \"{}\" of effect \"{}\" must have an arrow type, not {}",
+                op, effect, typ
+            ),
+            TypError::InCallback(effect) => write!(
+                f,
+                "Effect \"{}\" can't be performed inside a built-in function's callback, where its continuation can't be captured",
+                effect
+            ),
+            TypError::Many(errs) => {
+                for (i, err) in errs.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", err)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// a typed hole (?name) along with its expected type, if known, and the
+// bindings which could be used to fill it
+#[derive(Clone, Debug)]
+pub struct Hole {
+    pub name: String,
+    pub typ: Typ,
+    pub locals: Vec<(String, Typ)>,
+    pub candidates: Vec<String>,
+}
+
+impl std::fmt::Display for Hole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Found hole ?{} : {}", self.name, self.typ)?;
+        for (name, typ) in &self.locals {
+            write!(f, "\n    {} : {}", name, typ)?;
+        }
+        if !self.candidates.is_empty() {
+            write!(f, "\n  in scope with this type: {}", self.candidates.join(", "))?;
         }
+        Ok(())
     }
 }
 
+// bidirectional: typ synthesises the type of a term, check pushes a known
+// type inwards; errors are collected rather than stopping at the first
+//
+// inference stops being local in two places. unification variables stand
+// for what a term doesn't determine on its own, such as the type arguments
+// of a polymorphic global or the elements of an empty list, and may be
+// solved anywhere within the same top-level definition; that definition's
+// type is then generalised, which is the only place a let is polymorphic.
+// parameters are never inferred: a lambda without annotations needs a type
+// to be checked against
 impl Term {
     pub fn typ(&self, typ_ctx: &TypContext) -> TypResult<Typ> {
-        match self {
+        let mut typer = Typer::new(typ_ctx);
+        let typ = typer.infer(typ_ctx, self);
+        let typ = typer.generalize(self, &typ);
+        typer.finish(typ)
+    }
+
+    pub fn check(&self, typ_ctx: &TypContext, typ: &Typ) -> TypResult<()> {
+        let mut typer = Typer::new(typ_ctx);
+        typer.check(typ_ctx, self, typ);
+        typer.finish(())
+    }
+}
+
+// state of a single run of the type checker
+struct Typer {
+    globals: TypContext,
+    errors: Vec<TypError>,
+    // solutions to unification variables, indexed by Typ::Meta
+    metas: Vec<Option<Typ>>,
+    // number of loops around the statement being checked, within the
+    // innermost function
+    loops: usize,
+    // result type of the innermost function, if any
+    ret: Option<Typ>,
+    // effects which may be performed by the term being checked: those of
+    // the innermost function plus any handled around it
+    eff: Typ,
+    // types of map keys and set elements, checked to be ordered once known
+    keys: Vec<Typ>,
+    // effects of the callbacks given to built-in functions, checked to be
+    // at most IO once known
+    callbacks: Vec<Typ>,
+}
+
+impl Typer {
+    fn new(globals: &TypContext) -> Self {
+        Typer {
+            globals: globals.clone(),
+            errors: vec![],
+            metas: vec![],
+            loops: 0,
+            ret: None,
+            eff: Typ::pure(),
+            keys: vec![],
+            callbacks: vec![],
+        }
+    }
+
+    fn finish<T>(mut self, res: T) -> TypResult<T> {
+        let mut unordered: Vec<Typ> = vec![];
+        for key in std::mem::take(&mut self.keys) {
+            let key = self.resolve(&key);
+            if !key.is_ordered() && !unordered.contains(&key) {
+                unordered.push(key);
+            }
+        }
+        self.errors.extend(unordered.into_iter().map(TypError::Unordered));
+
+        let mut effects: Vec<String> = vec![];
+        for row in std::mem::take(&mut self.callbacks) {
+            let (effs, tail) = self.flatten_row(&row);
+            let tail = match tail {
+                Some(Typ::Var(name)) => Some(name),
+                _ => None,
+            };
+            for eff in effs.into_iter().filter(|e| e != "IO").chain(tail) {
+                if !effects.contains(&eff) {
+                    effects.push(eff);
+                }
+            }
+        }
+        self.errors.extend(effects.into_iter().map(TypError::InCallback));
+
+        let mut errors: Vec<_> = std::mem::take(&mut self.errors)
+            .into_iter()
+            .map(|e| self.resolve_error(e))
+            .collect();
+
+        match errors.len() {
+            0 => Ok(res),
+            1 => Err(errors.remove(0)),
+            _ => Err(TypError::Many(errors)),
+        }
+    }
+
+    fn error(&mut self, err: TypError) -> Typ {
+        // an undefined variable is reported once, however often it occurs
+        let reported = match &err {
+            TypError::Undefined { name, .. } => self.errors.iter().any(
+                |e| matches!(e, TypError::Undefined { name: other, .. } if other == name),
+            ),
+            _ => false,
+        };
+        if !reported {
+            self.errors.push(err);
+        }
+
+        Typ::Unknown
+    }
+
+    // replaces solved unification variables in an error, once checking is
+    // done and as much as possible is known
+    fn resolve_error(&self, err: TypError) -> TypError {
+        match err {
+            TypError::Mismatch(exp, rec) => {
+                TypError::Mismatch(self.resolve(&exp), self.resolve(&rec))
+            }
+            TypError::Expected(exp, rec) => TypError::Expected(exp, self.resolve(&rec)),
+            TypError::UnexpectedAbs(exp) => TypError::UnexpectedAbs(self.resolve(&exp)),
+            TypError::Unordered(typ) => TypError::Unordered(self.resolve(&typ)),
+            TypError::Hole(hole) => {
+                let typ = self.resolve(&hole.typ);
+                let locals: Vec<_> = hole
+                    .locals
+                    .into_iter()
+                    .map(|(name, t)| (name, self.resolve(&t)))
+                    .collect();
+
+                let candidates = match typ {
+                    Typ::Unknown | Typ::Meta(_) => vec![],
+                    _ => self
+                        .globals
+                        .iter()
+                        .filter(|(name, t)| **t == typ && !locals.iter().any(|(l, _)| l == *name))
+                        .map(|(name, _)| name.clone())
+                        .collect(),
+                };
+
+                TypError::Hole(Hole {
+                    name: hole.name,
+                    typ,
+                    locals,
+                    candidates,
+                })
+            }
+            err => err,
+        }
+    }
+
+    fn hole(&mut self, typ_ctx: &TypContext, name: &str, typ: &Typ) {
+        let locals = typ_ctx
+            .iter()
+            .filter(|(name, typ)| self.globals.get(*name) != Some(typ))
+            .map(|(name, typ)| (name.clone(), typ.clone()))
+            .collect();
+
+        self.error(TypError::Hole(Hole {
+            name: name.to_string(),
+            typ: typ.clone(),
+            locals,
+            candidates: vec![],
+        }));
+    }
+
+    // unification
+
+    fn fresh(&mut self) -> Typ {
+        self.metas.push(None);
+        Typ::Meta(self.metas.len() - 1)
+    }
+
+    // follows solved unification variables at the root of a type
+    fn shallow(&self, typ: &Typ) -> Typ {
+        match typ {
+            Typ::Meta(m) => match &self.metas[*m] {
+                Some(t) => self.shallow(t),
+                None => typ.clone(),
+            },
+            _ => typ.clone(),
+        }
+    }
+
+    // replaces every solved unification variable in a type
+    fn resolve(&self, typ: &Typ) -> Typ {
+        match self.shallow(typ) {
+            Typ::Func { from, to, eff } => {
+                Typ::func_eff(self.resolve(&from), self.resolve(&to), self.resolve(&eff))
+            }
+            row @ Typ::Row { .. } => {
+                let (effs, tail) = self.flatten_row(&row);
+                Typ::row(effs, tail)
+            }
+            Typ::Con { name, args } => Typ::Con {
+                name,
+                args: args.iter().map(|t| self.resolve(t)).collect(),
+            },
+            Typ::Forall { vars, typ } => Typ::Forall {
+                vars,
+                typ: Box::new(self.resolve(&typ)),
+            },
+            typ => typ,
+        }
+    }
+
+    fn occurs(&self, meta: usize, typ: &Typ) -> bool {
+        match self.shallow(typ) {
+            Typ::Meta(m) => m == meta,
+            Typ::Func { from, to, eff } => {
+                self.occurs(meta, &from) || self.occurs(meta, &to) || self.occurs(meta, &eff)
+            }
+            Typ::Row { tail, .. } => tail.is_some_and(|t| self.occurs(meta, &t)),
+            Typ::Con { args, .. } => args.iter().any(|t| self.occurs(meta, t)),
+            _ => false,
+        }
+    }
+
+    fn unify(&mut self, a: &Typ, b: &Typ) -> bool {
+        match (self.shallow(a), self.shallow(b)) {
+            (Typ::Unknown, _) | (_, Typ::Unknown) => true,
+            (Typ::Meta(m), Typ::Meta(n)) if m == n => true,
+            (Typ::Meta(m), t) | (t, Typ::Meta(m)) => {
+                if self.occurs(m, &t) {
+                    false
+                } else {
+                    self.metas[m] = Some(t);
+                    true
+                }
+            }
+            (Typ::Atom(a), Typ::Atom(b)) => a == b,
+            (Typ::Var(a), Typ::Var(b)) => a == b,
+            (a, b) if matches!(a, Typ::Row { .. }) || matches!(b, Typ::Row { .. }) => {
+                self.unify_rows(&a, &b)
+            }
+            (
+                Typ::Func {
+                    from: f1,
+                    to: t1,
+                    eff: e1,
+                },
+                Typ::Func {
+                    from: f2,
+                    to: t2,
+                    eff: e2,
+                },
+            ) => self.unify(&f1, &f2) && self.unify(&t1, &t2) && self.unify(&e1, &e2),
+            (Typ::Con { name: n1, args: a1 }, Typ::Con { name: n2, args: a2 }) => {
+                n1 == n2
+                    && a1.len() == a2.len()
+                    && a1.iter().zip(&a2).all(|(a, b)| self.unify(a, b))
+            }
+            _ => false,
+        }
+    }
+
+    // the effects of a row and its tail, if it's open
+    fn flatten_row(&self, row: &Typ) -> (Vec<String>, Option<Typ>) {
+        match self.shallow(row) {
+            Typ::Row { mut effs, tail } => {
+                let (more, tail) = match tail {
+                    Some(tail) => self.flatten_row(&tail),
+                    None => (vec![], None),
+                };
+
+                for eff in more {
+                    if !effs.contains(&eff) {
+                        effs.push(eff);
+                    }
+                }
+
+                (effs, tail)
+            }
+            tail => (vec![], Some(tail)),
+        }
+    }
+
+    // rows are equal if they have the same effects, in any order: an open row
+    // lacking some of the effects of the other is extended with them
+    fn unify_rows(&mut self, a: &Typ, b: &Typ) -> bool {
+        let (effs_a, tail_a) = self.flatten_row(a);
+        let (effs_b, tail_b) = self.flatten_row(b);
+
+        let only_a: Vec<_> = effs_a.iter().filter(|e| !effs_b.contains(e)).cloned().collect();
+        let only_b: Vec<_> = effs_b.iter().filter(|e| !effs_a.contains(e)).cloned().collect();
+
+        match (tail_a, tail_b) {
+            (None, None) => only_a.is_empty() && only_b.is_empty(),
+            (None, Some(tail_b)) => only_b.is_empty() && self.extend_row(&tail_b, only_a, None),
+            (Some(tail_a), None) => only_a.is_empty() && self.extend_row(&tail_a, only_b, None),
+            (Some(tail_a), Some(tail_b)) => {
+                if only_a.is_empty() && only_b.is_empty() {
+                    return self.unify(&tail_a, &tail_b);
+                }
+                if tail_a == tail_b && tail_a != Typ::Unknown {
+                    return false;
+                }
+
+                let rest = self.fresh();
+                self.extend_row(&tail_a, only_b, Some(rest.clone()))
+                    && self.extend_row(&tail_b, only_a, Some(rest))
+            }
+        }
+    }
+
+    // solves the tail of a row to the given effects and rest
+    fn extend_row(&mut self, tail: &Typ, effs: Vec<String>, rest: Option<Typ>) -> bool {
+        match tail {
+            Typ::Meta(_) | Typ::Unknown => self.unify(tail, &Typ::row(effs, rest)),
+            // a row variable can't be extended
+            _ => effs.is_empty() && rest.is_some_and(|rest| self.unify(tail, &rest)),
+        }
+    }
+
+    // requires the effect to be allowed where the term being checked runs
+    fn perform(&mut self, effect: &str) {
+        let (effs, tail) = self.flatten_row(&self.eff.clone());
+        if effs.iter().any(|e| e == effect) {
+            return;
+        }
+
+        let extended = match tail {
+            Some(tail) => {
+                let rest = self.fresh();
+                self.extend_row(&tail, vec![effect.to_string()], Some(rest))
+            }
+            None => false,
+        };
+
+        if !extended {
+            self.error(TypError::Unhandled(effect.to_string()));
+        }
+    }
+
+    // requires the effects of a row, e.g. of a function being applied, to be
+    // allowed where the term being checked runs
+    fn include(&mut self, row: &Typ) {
+        let (effs, tail) = self.flatten_row(row);
+        for eff in &effs {
+            self.perform(eff);
+        }
+
+        let eff = self.eff.clone();
+        match tail {
+            None | Some(Typ::Unknown) => {}
+            // a built-in performs just the effects of its callback, which
+            // have been found by now
+            Some(tail @ Typ::Meta(_)) if self.is_callback(&tail) => {
+                self.unify(&tail, &Typ::pure());
+            }
+            // the function performs whatever effects are allowed here
+            Some(tail @ Typ::Meta(_)) => {
+                if !self.unify(&tail, &eff) {
+                    self.error(TypError::Mismatch(eff, tail));
+                }
+            }
+            Some(tail) => match self.flatten_row(&eff).1 {
+                Some(Typ::Unknown) => {}
+                Some(eff_tail) if eff_tail == tail => {}
+                Some(eff_tail @ Typ::Meta(_)) => {
+                    self.unify(&eff_tail, &tail);
+                }
+                _ => {
+                    self.error(TypError::Unhandled(tail.to_string()));
+                }
+            },
+        }
+    }
+
+    fn is_callback(&self, tail: &Typ) -> bool {
+        self.callbacks
+            .iter()
+            .any(|row| self.flatten_row(row).1.as_ref() == Some(tail))
+    }
+
+    fn expect(&mut self, exp: &Typ, rec: &Typ) {
+        if !self.unify(exp, rec) {
+            self.error(TypError::Mismatch(exp.clone(), rec.clone()));
+        }
+    }
+
+    // replaces the variables bound by a forall with fresh unification
+    // variables
+    fn instantiate(&mut self, typ: &Typ) -> Typ {
+        match typ {
+            Typ::Forall { vars, typ } => {
+                let sub = vars
+                    .iter()
+                    .fold(crate::Map::new(), |sub, v| sub.insert(v.clone(), self.fresh()));
+
+                if let Some(row) = sub.get(CALLBACK_ROW) {
+                    self.callbacks.push(row.clone());
+                }
+
+                let typ = typ.subst_vars(&sub);
+                self.keys(&typ);
+                typ
+            }
+            Typ::Op {
+                effect,
+                param,
+                result,
+            } => Typ::func_eff(
+                *param.clone(),
+                *result.clone(),
+                Typ::row(vec![effect.clone()], None),
+            ),
+            _ => typ.clone(),
+        }
+    }
+
+    // remembers the keys of the maps and sets in the type
+    fn keys(&mut self, typ: &Typ) {
+        match typ {
+            Typ::Func { from, to, .. }
+            | Typ::Op {
+                param: from,
+                result: to,
+                ..
+            } => {
+                self.keys(from);
+                self.keys(to);
+            }
+            Typ::Con { name, args } => {
+                if name == "Map" || name == "Set" {
+                    self.keys.push(args[0].clone());
+                }
+                args.iter().for_each(|arg| self.keys(arg));
+            }
+            Typ::Forall { typ, .. } => self.keys(typ),
+            _ => {}
+        }
+    }
+
+    // quantifies over the type variables and unsolved metas of a value;
+    // unconstrained effects are taken to be pure
+    fn generalize(&mut self, term: &Term, typ: &Typ) -> Typ {
+        let mut metas = vec![];
+        let mut rows = vec![];
+        self.collect_metas(typ, &mut metas, &mut rows);
+
+        for m in rows {
+            self.metas[m] = Some(Typ::pure());
+        }
+        let typ = self.resolve(typ);
+
+        let mut vars = typ.vars();
+        if !term.is_value() {
+            if !metas.is_empty() {
+                return self.error(TypError::Ambiguous(typ));
+            }
+            if !vars.is_empty() {
+                return self.error(TypError::NotGeneralizable(typ));
+            }
+        }
+
+        let mut names = ('a'..='z').map(|c| c.to_string());
+        for m in metas {
+            let name = names.find(|n| !vars.contains(n)).unwrap();
+            self.metas[m] = Some(Typ::var(&name));
+            vars.push(name);
+        }
+
+        if vars.is_empty() {
+            typ
+        } else {
+            Typ::Forall {
+                vars,
+                typ: Box::new(self.resolve(&typ)),
+            }
+        }
+    }
+
+    // collects the unsolved unification variables standing for types into
+    // metas, and those standing for rows of effects into rows
+    fn collect_metas(&self, typ: &Typ, metas: &mut Vec<usize>, rows: &mut Vec<usize>) {
+        match self.shallow(typ) {
+            Typ::Meta(m) if !metas.contains(&m) => metas.push(m),
+            Typ::Func { from, to, eff } => {
+                self.collect_metas(&from, metas, rows);
+                self.collect_metas(&to, metas, rows);
+
+                if let (_, Some(Typ::Meta(m))) = self.flatten_row(&eff) {
+                    if !rows.contains(&m) {
+                        rows.push(m);
+                    }
+                }
+            }
+            Typ::Con { args, .. } => args.iter().for_each(|t| self.collect_metas(t, metas, rows)),
+            _ => {}
+        }
+    }
+
+    // checking
+
+    fn infer(&mut self, typ_ctx: &TypContext, term: &Term) -> Typ {
+        match term {
             Term::Var { name } => match typ_ctx.get(name) {
-                Some(t) => Ok(t.clone()),
-                None => Err(TypError::Undefined(name.clone())),
+                Some(t) => self.instantiate(t),
+                None => self.error(TypError::Undefined {
+                    name: name.clone(),
+                    similar: suggest(name, typ_ctx.keys().map(String::as_str)),
+                    later: vec![],
+                }),
             },
             Term::Abs {
                 param_name,
                 param_typ,
                 body,
             } => {
-                let typ_ctx = if param_name == "_" {
-                    typ_ctx.clone()
-                } else {
-                    typ_ctx.insert(param_name.clone(), param_typ.clone())
+                let param_typ = match param_typ {
+                    Some(t) => {
+                        self.keys(t);
+                        t.clone()
+                    }
+                    None => self.error(TypError::Unannotated(param_name.clone())),
                 };
 
-                let body_typ = body.typ(&typ_ctx)?;
-                Ok(Typ::func(param_typ.clone(), body_typ.clone()))
+                let typ_ctx = bind(typ_ctx, param_name, &param_typ);
+
+                let eff = self.fresh();
+                let ret = self.infer_body(&typ_ctx, body, &eff);
+
+                Typ::func_eff(param_typ, ret, eff)
             }
             Term::App { func, arg } => {
-                let func_typ = func.typ(&typ_ctx)?;
-                let arg_typ = arg.typ(&typ_ctx)?;
+                // (fun x => body) arg, e.g. from a pipeline: the type of x is
+                // that of the argument
+                if let Term::Abs {
+                    param_name,
+                    param_typ: None,
+                    body,
+                } = func.as_ref()
+                {
+                    let arg_typ = self.infer(typ_ctx, arg);
+                    let typ_ctx = bind(typ_ctx, param_name, &arg_typ);
 
-                match &func_typ {
-                    Typ::Func { from, to } => {
-                        let from = *from.clone();
+                    // its body runs right away
+                    let eff = self.eff.clone();
+                    return self.infer_body(&typ_ctx, body, &eff);
+                }
 
-                        if from == arg_typ {
-                            Ok(*to.clone())
-                        } else {
-                            Err(TypError::Mismatch(from, arg_typ))
-                        }
-                    }
-                    _ => Err(TypError::Expected("arrow type".to_string(), func_typ)),
+                let (from, to, eff) = self.infer_func(typ_ctx, func);
+                self.check(typ_ctx, arg, &from);
+                self.include(&eff);
+
+                to
+            }
+            Term::Compose { first, second } => {
+                let (from, mid, eff) = self.infer_func(typ_ctx, first);
+                let (mid2, to, eff2) = self.infer_func(typ_ctx, second);
+
+                self.expect(&mid2, &mid);
+                self.expect(&eff, &eff2);
+                Typ::func_eff(from, to, eff)
+            }
+
+            Term::Int(_) => Typ::atom("Int"),
+
+            Term::List(items) => {
+                let elem = self.fresh();
+                for item in items {
+                    self.check(typ_ctx, item, &elem);
                 }
+
+                Typ::list(elem)
             }
+            Term::Cons { head, tail } => {
+                let elem = self.infer(typ_ctx, head);
+                let typ = Typ::list(elem);
+                self.check(typ_ctx, tail, &typ);
 
-            Term::Int(_) => Ok(Typ::atom("Int")),
+                typ
+            }
+            Term::Pair { fst, snd } => {
+                let fst = self.infer(typ_ctx, fst);
+                let snd = self.infer(typ_ctx, snd);
+
+                Typ::pair(fst, snd)
+            }
+
+            Term::Ascribe { term, typ } => {
+                self.keys(typ);
+                self.check(typ_ctx, term, typ);
+                typ.clone()
+            }
+            Term::Ref(term) => Typ::ref_(self.infer(typ_ctx, term)),
+            Term::Deref(term) => {
+                let typ = self.fresh();
+                self.check(typ_ctx, term, &Typ::ref_(typ.clone()));
+
+                typ
+            }
+            Term::Assign { target, val } => {
+                let typ = self.fresh();
+                self.check(typ_ctx, target, &Typ::ref_(typ.clone()));
+                self.check(typ_ctx, val, &typ);
+
+                Typ::atom("Unit")
+            }
+
+            Term::Hole(name) => {
+                let typ = self.fresh();
+                self.hole(typ_ctx, name, &typ);
+                typ
+            }
 
             Term::If {
                 cond,
                 t_true,
                 t_false,
             } => {
-                let typ_cond = cond.typ(&typ_ctx)?;
-                if typ_cond != Typ::atom("Bool") {
-                    return Err(TypError::Mismatch(Typ::atom("Bool"), typ_cond));
-                }
+                self.check(typ_ctx, cond, &Typ::atom("Bool"));
 
-                let typ_true = t_true.typ(&typ_ctx)?;
-                let typ_false = t_false.typ(&typ_ctx)?;
+                let typ_true = self.infer(typ_ctx, t_true);
+                self.check(typ_ctx, t_false, &typ_true);
 
-                if typ_true == typ_false {
-                    Ok(typ_true)
-                } else {
-                    Err(TypError::Mismatch(typ_true, typ_false))
-                }
+                typ_true
+            }
+            Term::Match { scrutinee, arms } => {
+                let typ = self.fresh();
+                self.check_arms(typ_ctx, scrutinee, arms, &typ);
+
+                typ
+            }
+            Term::Raise(exn) => {
+                self.check(typ_ctx, exn, &Typ::exn());
+                self.fresh()
             }
+            Term::Try { body, pat, handler } => {
+                let typ = self.infer(typ_ctx, body);
+                self.check_handler(typ_ctx, pat, handler, &typ);
+
+                typ
+            }
+
+            Term::Perform { op, arg } => match typ_ctx.get(op) {
+                Some(Typ::Op {
+                    effect,
+                    param,
+                    result,
+                }) => {
+                    self.check(typ_ctx, arg, param);
+                    self.perform(effect);
+
+                    *result.clone()
+                }
+                _ => self.error(TypError::NotAnOperation(op.clone())),
+            },
+            Term::Handle { body, handler } => self.infer_handle(typ_ctx, body, handler, None),
+
             Term::Seq(stmts) => {
                 let mut typ_ctx = typ_ctx.clone();
 
                 let mut typ_end = Typ::atom("Unit");
                 for stmt in stmts {
-                    typ_end = match stmt {
-                        Stmt::Term(term) => term.typ(&typ_ctx)?,
-                        Stmt::Let(var, term) => {
-                            let t = term.typ(&typ_ctx)?;
-                            typ_ctx = typ_ctx.insert(var.to_string(), t.clone());
-                            t
+                    typ_end = self.infer_stmt(&mut typ_ctx, stmt);
+                }
+
+                typ_end
+            }
+        }
+    }
+
+    // the handled term may perform the effect of the handler's operations on
+    // top of those allowed around it
+    fn infer_handle(
+        &mut self,
+        typ_ctx: &TypContext,
+        body: &Term,
+        handler: &Handler,
+        expected: Option<&Typ>,
+    ) -> Typ {
+        let mut effect: Option<String> = None;
+        let mut ops = vec![];
+        for clause in &handler.ops {
+            match typ_ctx.get(&clause.op) {
+                Some(Typ::Op {
+                    effect: op_effect,
+                    param,
+                    result,
+                }) => {
+                    if let Some(effect) = &effect {
+                        if effect != op_effect {
+                            self.error(TypError::WrongEffect {
+                                op: clause.op.clone(),
+                                effect: effect.clone(),
+                            });
+                            continue;
+                        }
+                    }
+
+                    effect = Some(op_effect.clone());
+                    ops.push((clause, *param.clone(), *result.clone()));
+                }
+                _ => {
+                    self.error(TypError::NotAnOperation(clause.op.clone()));
+                }
+            }
+        }
+
+        let outer = self.eff.clone();
+        if let Some(effect) = &effect {
+            for (name, typ) in typ_ctx.iter() {
+                if let Typ::Op { effect: op_effect, .. } = typ {
+                    if op_effect == effect && !handler.ops.iter().any(|c| &c.op == name) {
+                        self.error(TypError::MissingOperation {
+                            effect: effect.clone(),
+                            op: name.clone(),
+                        });
+                    }
+                }
+            }
+
+            self.eff = Typ::row(vec![effect.clone()], Some(outer.clone()));
+        }
+        let body_typ = self.infer(typ_ctx, body);
+        self.eff = outer.clone();
+
+        let typ = match (&handler.ret, expected) {
+            (Some((x, term)), Some(typ)) => {
+                self.check(&bind(typ_ctx, x, &body_typ), term, typ);
+                typ.clone()
+            }
+            (Some((x, term)), None) => self.infer(&bind(typ_ctx, x, &body_typ), term),
+            (None, Some(typ)) => {
+                self.expect(typ, &body_typ);
+                typ.clone()
+            }
+            (None, None) => body_typ,
+        };
+
+        // resuming the continuation runs the rest of the handled term, under
+        // the handler again
+        for (clause, param, result) in ops {
+            let k = Typ::func_eff(result, typ.clone(), outer.clone());
+            let clause_ctx = bind(&bind(typ_ctx, &clause.param, &param), &clause.k, &k);
+            self.check(&clause_ctx, &clause.body, &typ);
+        }
+
+        typ
+    }
+
+    // infers the type of a term used as a function, as (from, to, effects)
+    fn infer_func(&mut self, typ_ctx: &TypContext, term: &Term) -> (Typ, Typ, Typ) {
+        let typ = self.infer(typ_ctx, term);
+        match self.split_func(&typ) {
+            Some(split) => split,
+            None => {
+                self.error(TypError::Expected("arrow type".to_string(), typ));
+                (Typ::Unknown, Typ::Unknown, Typ::Unknown)
+            }
+        }
+    }
+
+    // the parameter and result types of a function type, along with its
+    // effects
+    fn split_func(&mut self, typ: &Typ) -> Option<(Typ, Typ, Typ)> {
+        match self.shallow(typ) {
+            Typ::Func { from, to, eff } => Some((*from, *to, *eff)),
+            Typ::Unknown => Some((Typ::Unknown, Typ::Unknown, Typ::Unknown)),
+            meta @ Typ::Meta(_) => {
+                let (from, to, eff) = (self.fresh(), self.fresh(), self.fresh());
+                self.unify(&meta, &Typ::func_eff(from.clone(), to.clone(), eff.clone()));
+                Some((from, to, eff))
+            }
+            _ => None,
+        }
+    }
+
+    // types the statement, adding any binding it introduces to typ_ctx
+    fn infer_stmt(&mut self, typ_ctx: &mut TypContext, stmt: &Stmt) -> Typ {
+        match stmt {
+            Stmt::Term(term) => self.infer(typ_ctx, term),
+            Stmt::Let(var, term) => {
+                let t = self.infer(typ_ctx, term);
+                *typ_ctx = typ_ctx.insert(var.to_string(), t.clone());
+                t
+            }
+
+            Stmt::While { cond, body } => {
+                self.check(typ_ctx, cond, &Typ::atom("Bool"));
+                self.infer_loop(typ_ctx, body)
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                self.check(typ_ctx, from, &Typ::atom("Int"));
+                self.check(typ_ctx, to, &Typ::atom("Int"));
+                self.infer_loop(&bind(typ_ctx, var, &Typ::atom("Int")), body)
+            }
+            // jumps, so they can take any type
+            Stmt::Break | Stmt::Continue => {
+                if self.loops == 0 {
+                    return self.error(TypError::OutsideLoop(stmt.to_string()));
+                }
+
+                self.fresh()
+            }
+            Stmt::Return(term) => {
+                match self.ret.clone() {
+                    Some(ret) => {
+                        self.check(typ_ctx, term, &ret);
+                        self.fresh()
+                    }
+                    None => self.error(TypError::OutsideFunc),
+                }
+            }
+        }
+    }
+
+    fn infer_loop(&mut self, typ_ctx: &TypContext, body: &[Stmt]) -> Typ {
+        let mut typ_ctx = typ_ctx.clone();
+
+        self.loops += 1;
+        for stmt in body {
+            self.infer_stmt(&mut typ_ctx, stmt);
+        }
+        self.loops -= 1;
+
+        Typ::atom("Unit")
+    }
+
+    // infers the result type of a function from its body, taking it from
+    // the annotation if there's one so that early returns are checked
+    // against it
+    fn infer_body(&mut self, typ_ctx: &TypContext, body: &Term, eff: &Typ) -> Typ {
+        let ret = match body {
+            Term::Ascribe { typ, .. } => typ.clone(),
+            _ => self.fresh(),
+        };
+
+        let body_typ = self.in_func(&ret, eff, |typer| typer.infer(typ_ctx, body));
+        self.expect(&ret, &body_typ);
+
+        ret
+    }
+
+    // checks the body of a function with the given result type and effects,
+    // which can't break out of the loops around the function
+    fn in_func<T>(&mut self, ret: &Typ, eff: &Typ, f: impl FnOnce(&mut Self) -> T) -> T {
+        let loops = std::mem::take(&mut self.loops);
+        let ret = self.ret.replace(ret.clone());
+        let eff = std::mem::replace(&mut self.eff, eff.clone());
+        let res = f(self);
+        self.loops = loops;
+        self.ret = ret;
+        self.eff = eff;
+
+        res
+    }
+
+    fn check_arms(&mut self, typ_ctx: &TypContext, scrutinee: &Term, arms: &[(Pat, Term)], typ: &Typ) {
+        let scrutinee_typ = self.infer(typ_ctx, scrutinee);
+
+        for (pat, body) in arms {
+            let mut arm_ctx = typ_ctx.clone();
+            self.check_pat(&mut arm_ctx, pat, &scrutinee_typ);
+            self.check(&arm_ctx, body, typ);
+        }
+    }
+
+    fn check_handler(&mut self, typ_ctx: &TypContext, pat: &Pat, handler: &Term, typ: &Typ) {
+        let mut handler_ctx = typ_ctx.clone();
+        self.check_pat(&mut handler_ctx, pat, &Typ::exn());
+        self.check(&handler_ctx, handler, typ)
+    }
+
+    // checks a pattern against the type of the values it matches, adding the
+    // variables it binds to typ_ctx
+    fn check_pat(&mut self, typ_ctx: &mut TypContext, pat: &Pat, typ: &Typ) {
+        match pat {
+            Pat::Wild => {}
+            Pat::Var(name) => *typ_ctx = typ_ctx.insert(name.clone(), typ.clone()),
+            Pat::Int(_) => self.expect(typ, &Typ::atom("Int")),
+            Pat::Con(name, args) => {
+                let con_typ = match typ_ctx.get(name) {
+                    Some(t) => self.instantiate(t),
+                    None => self.error(TypError::Undefined {
+                        name: name.clone(),
+                        similar: suggest(name, typ_ctx.keys().map(String::as_str)),
+                        later: vec![],
+                    }),
+                };
+
+                let mut res_typ = con_typ.clone();
+                for arg in args {
+                    match self.split_func(&res_typ) {
+                        Some((from, to, _)) => {
+                            self.check_pat(typ_ctx, arg, &from);
+                            res_typ = to;
+                        }
+                        None => {
+                            self.error(TypError::Arity {
+                                con: name.clone(),
+                                expected: arity(&con_typ),
+                                found: args.len(),
+                            });
+                            return;
+                        }
+                    }
+                }
+
+                if let Typ::Func { .. } = self.shallow(&res_typ) {
+                    self.error(TypError::Arity {
+                        con: name.clone(),
+                        expected: arity(&con_typ),
+                        found: args.len(),
+                    });
+                    return;
+                }
+
+                self.expect(typ, &res_typ)
+            }
+            Pat::Nil => {
+                let elem = self.fresh();
+                self.expect(typ, &Typ::list(elem))
+            }
+            Pat::Cons(head, tail) => {
+                let elem = self.fresh();
+                let list = Typ::list(elem.clone());
+                self.expect(typ, &list);
+
+                self.check_pat(typ_ctx, head, &elem);
+                self.check_pat(typ_ctx, tail, &list)
+            }
+            Pat::Pair(fst, snd) => {
+                let (fst_typ, snd_typ) = (self.fresh(), self.fresh());
+                self.expect(typ, &Typ::pair(fst_typ.clone(), snd_typ.clone()));
+
+                self.check_pat(typ_ctx, fst, &fst_typ);
+                self.check_pat(typ_ctx, snd, &snd_typ)
+            }
+        }
+    }
+
+    fn check(&mut self, typ_ctx: &TypContext, term: &Term, typ: &Typ) {
+        match term {
+            Term::Abs {
+                param_name,
+                param_typ,
+                body,
+            } => match self.split_func(typ) {
+                Some((from, to, eff)) => {
+                    if let Some(param_typ) = param_typ {
+                        self.keys(param_typ);
+                        self.expect(&from, param_typ);
+                    }
+
+                    let typ_ctx = bind(typ_ctx, param_name, &from);
+                    self.in_func(&to, &eff, |typer| typer.check(&typ_ctx, body, &to))
+                }
+                None => {
+                    self.error(TypError::UnexpectedAbs(typ.clone()));
+                }
+            },
+
+            Term::Ascribe {
+                term,
+                typ: term_typ,
+            } => {
+                self.keys(term_typ);
+                self.check(typ_ctx, term, term_typ);
+                self.expect(typ, term_typ)
+            }
+            Term::Hole(name) => self.hole(typ_ctx, name, typ),
+
+            Term::If {
+                cond,
+                t_true,
+                t_false,
+            } => {
+                self.check(typ_ctx, cond, &Typ::atom("Bool"));
+                self.check(typ_ctx, t_true, typ);
+                self.check(typ_ctx, t_false, typ)
+            }
+            Term::Match { scrutinee, arms } => self.check_arms(typ_ctx, scrutinee, arms, typ),
+            Term::Try { body, pat, handler } => {
+                self.check(typ_ctx, body, typ);
+                self.check_handler(typ_ctx, pat, handler, typ)
+            }
+            Term::Handle { body, handler } => {
+                self.infer_handle(typ_ctx, body, handler, Some(typ));
+            }
+            Term::Seq(stmts) => {
+                let mut typ_ctx = typ_ctx.clone();
+
+                match stmts.split_last() {
+                    None => self.expect(typ, &Typ::atom("Unit")),
+                    Some((last, stmts)) => {
+                        for stmt in stmts {
+                            self.infer_stmt(&mut typ_ctx, stmt);
                         }
-                    };
+
+                        match last {
+                            Stmt::Term(term) | Stmt::Let(_, term) => {
+                                self.check(&typ_ctx, term, typ)
+                            }
+                            stmt => {
+                                let stmt_typ = self.infer_stmt(&mut typ_ctx, stmt);
+                                self.expect(typ, &stmt_typ)
+                            }
+                        }
+                    }
                 }
+            }
 
-                Ok(typ_end)
+            _ => {
+                let term_typ = self.infer(typ_ctx, term);
+                self.expect(typ, &term_typ)
             }
         }
     }
 }
+
+// number of arguments taken by a function (or constructor) of the given type
+fn arity(typ: &Typ) -> usize {
+    match typ {
+        Typ::Func { to, .. } => 1 + arity(to),
+        _ => 0,
+    }
+}
+
+fn bind(typ_ctx: &TypContext, name: &str, typ: &Typ) -> TypContext {
+    if name == "_" {
+        typ_ctx.clone()
+    } else {
+        typ_ctx.insert(name.to_string(), typ.clone())
+    }
+}
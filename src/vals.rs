@@ -1,7 +1,13 @@
+use crate::cast;
+use crate::terms::Handler;
+use crate::terms::Pat;
 use crate::terms::Stmt;
 use crate::terms::Term;
 
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicUsize};
 
 // values
 
@@ -10,26 +16,316 @@ pub enum Val {
     Abs {
         val_ctx: ValContext,
         param: String,
-        body: Term,
+        body: Rc<Term>,
+        // how the body passes arguments on, i.e. that of the term the
+        // function was defined in
+        strategy: Strategy,
     },
     Native(Rc<dyn Fn(Val) -> ValResult<Val>>),
+    // argument or variable evaluated by need, only ever seen in contexts
+    Thunk(Rc<RefCell<Thunk>>),
 
     Bool(bool),
     Int(i32),
     Unit,
+
+    List(List),
+    Pair(Rc<(Val, Val)>),
+    // value built by a constructor, e.g. Some 3
+    Con(String, Vec<Val>),
+
+    Map(crate::Map<Key, Val>),
+    Set(crate::Set<Key>),
+
+    // mutable cell, shared by every copy of the value
+    Ref(Rc<RefCell<Val>>),
+    // mutable array, its storage is also shared by every copy
+    Array(Rc<RefCell<Vec<Val>>>),
+
+    Stream(Stream),
+
+    // operation of an effect, performed when applied
+    Op(String),
+    // continuation captured by a handler, resumed on top of the current one
+    // when applied
+    Cont(Rc<Cont>),
+    // callcc, which captures the rest of the computation when applied
+    CallCC,
+    // continuation captured by callcc, replacing the current one of the
+    // machine that captured it when applied
+    Escape {
+        machine: usize,
+        frames: Rc<Vec<Frame>>,
+    },
+}
+
+// a value used as the key of a map or an element of a set, which must not
+// contain any functions so that it can be ordered
+#[derive(Clone, Debug)]
+pub struct Key(Val);
+
+impl Key {
+    pub fn new(val: Val) -> ValResult<Self> {
+        if val.is_ordered() {
+            Ok(Key(val))
+        } else {
+            Err(ValError::Unordered(val))
+        }
+    }
+
+    pub fn val(&self) -> &Val {
+        &self.0
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+// total order on values without functions; values of different variants
+// are never compared by well-typed programs, but are still ordered by variant
+fn compare(x: &Val, y: &Val) -> Ordering {
+    match (x, y) {
+        (Val::Bool(a), Val::Bool(b)) => a.cmp(b),
+        (Val::Int(a), Val::Int(b)) => a.cmp(b),
+        (Val::Unit, Val::Unit) => Ordering::Equal,
+        (Val::List(a), Val::List(b)) => compare_all(a.iter(), b.iter()),
+        (Val::Pair(a), Val::Pair(b)) => compare(&a.0, &b.0).then_with(|| compare(&a.1, &b.1)),
+        (Val::Con(a, a_args), Val::Con(b, b_args)) => {
+            a.cmp(b).then_with(|| compare_all(a_args.iter(), b_args.iter()))
+        }
+        (Val::Map(a), Val::Map(b)) => compare_all(
+            a.iter().flat_map(|(k, v)| [k.val(), v]),
+            b.iter().flat_map(|(k, v)| [k.val(), v]),
+        ),
+        (Val::Set(a), Val::Set(b)) => compare_all(a.iter().map(Key::val), b.iter().map(Key::val)),
+        _ => rank(x).cmp(&rank(y)),
+    }
+}
+
+// lexicographic order on sequences of values
+fn compare_all<'a>(
+    xs: impl Iterator<Item = &'a Val>,
+    mut ys: impl Iterator<Item = &'a Val>,
+) -> Ordering {
+    for x in xs {
+        match ys.next() {
+            None => return Ordering::Greater,
+            Some(y) => match compare(x, y) {
+                Ordering::Equal => {}
+                ord => return ord,
+            },
+        }
+    }
+
+    match ys.next() {
+        None => Ordering::Equal,
+        Some(_) => Ordering::Less,
+    }
+}
+
+fn rank(val: &Val) -> usize {
+    match val {
+        Val::Abs { .. }
+        | Val::Native(_)
+        | Val::Thunk(_)
+        | Val::Ref(_)
+        | Val::Array(_)
+        | Val::Stream(_)
+        | Val::Op(_)
+        | Val::Cont(_)
+        | Val::CallCC
+        | Val::Escape { .. } => unreachable!(),
+        Val::Bool(_) => 0,
+        Val::Int(_) => 1,
+        Val::Unit => 2,
+        Val::List(_) => 3,
+        Val::Pair(_) => 4,
+        Val::Con(_, _) => 5,
+        Val::Map(_) => 6,
+        Val::Set(_) => 7,
+    }
+}
+
+// persistent singly linked list, sharing its tail with the lists it was
+// consed onto
+#[derive(Clone, Default)]
+pub struct List {
+    head: Option<Rc<(Val, List)>>,
+}
+
+impl List {
+    pub fn nil() -> Self {
+        List { head: None }
+    }
+
+    pub fn cons(head: Val, tail: List) -> Self {
+        List {
+            head: Some(Rc::new((head, tail))),
+        }
+    }
+
+    pub fn uncons(&self) -> Option<(&Val, &List)> {
+        self.head.as_ref().map(|cell| (&cell.0, &cell.1))
+    }
+
+    pub fn iter(&self) -> ListIter<'_> {
+        ListIter { list: self }
+    }
+}
+
+impl FromIterator<Val> for List {
+    fn from_iter<I: IntoIterator<Item = Val>>(iter: I) -> Self {
+        let vals: Vec<_> = iter.into_iter().collect();
+        vals.into_iter()
+            .rev()
+            .fold(List::nil(), |tail, head| List::cons(head, tail))
+    }
+}
+
+// dropping a long list recursively would overflow the stack
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(cell) = next {
+            next = match Rc::try_unwrap(cell) {
+                Ok((_, mut tail)) => tail.head.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+pub struct ListIter<'a> {
+    list: &'a List,
+}
+
+impl<'a> Iterator for ListIter<'a> {
+    type Item = &'a Val;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (head, tail) = self.list.uncons()?;
+        self.list = tail;
+        Some(head)
+    }
+}
+
+// lazy, possibly infinite stream, whose cells are computed when first
+// forced and then kept
+#[derive(Clone)]
+pub struct Stream(Rc<RefCell<StreamCell>>);
+
+// the head and tail of a stream, or None if it's empty
+pub type StreamStep = Option<(Val, Stream)>;
+
+enum StreamCell {
+    Delayed(Rc<dyn Fn() -> ValResult<StreamStep>>),
+    Forced(StreamStep),
+}
+
+impl Stream {
+    pub fn nil() -> Self {
+        Stream(Rc::new(RefCell::new(StreamCell::Forced(None))))
+    }
+
+    pub fn cons(head: Val, tail: Stream) -> Self {
+        Stream(Rc::new(RefCell::new(StreamCell::Forced(Some((head, tail))))))
+    }
+
+    pub fn delay<F: Fn() -> ValResult<StreamStep> + 'static>(f: F) -> Self {
+        Stream(Rc::new(RefCell::new(StreamCell::Delayed(Rc::new(f)))))
+    }
+
+    pub fn force(&self) -> ValResult<StreamStep> {
+        let f = match &*self.0.borrow() {
+            StreamCell::Forced(cell) => return Ok(cell.clone()),
+            StreamCell::Delayed(f) => f.clone(),
+        };
+
+        let cell = f()?;
+        *self.0.borrow_mut() = StreamCell::Forced(cell.clone());
+
+        Ok(cell)
+    }
+}
+
+// dropping a long forced stream recursively would overflow the stack
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.0) != 1 {
+            return;
+        }
+
+        let mut next = match self.0.replace(StreamCell::Forced(None)) {
+            StreamCell::Forced(Some((_, tail))) => tail,
+            _ => return,
+        };
+        while Rc::strong_count(&next.0) == 1 {
+            next = match next.0.replace(StreamCell::Forced(None)) {
+                StreamCell::Forced(Some((_, tail))) => tail,
+                _ => return,
+            };
+        }
+    }
 }
 
 impl std::fmt::Debug for Val {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Val::Abs { val_ctx, param, body } => {
-                write!(f, "Abs {{ val_ctx: {:?}, param: {:?}, body: {:?} }}", val_ctx, param, body)
-            }
+            Val::Abs {
+                val_ctx,
+                param,
+                body,
+                strategy,
+            } => write!(
+                f,
+                "Abs {{ val_ctx: {:?}, param: {:?}, body: {:?}, strategy: {:?} }}",
+                val_ctx, param, body, strategy
+            ),
             Val::Native(_) => write!(f, "Native"),
+            Val::Thunk(thunk) => match &*thunk.borrow() {
+                Thunk::Delayed(term, _) => write!(f, "Thunk({:?})", term),
+                Thunk::Forced(val) => write!(f, "Thunk({:?})", val),
+            },
 
             Val::Bool(b) => write!(f, "Bool({})", *b),
             Val::Int(i) => write!(f, "Int({})", *i),
             Val::Unit => write!(f, "Unit"),
+
+            Val::List(list) => f.debug_list().entries(list.iter()).finish(),
+            Val::Pair(pair) => write!(f, "Pair({:?}, {:?})", pair.0, pair.1),
+            Val::Con(name, args) => write!(f, "Con({}, {:?})", name, args),
+
+            Val::Map(map) => f
+                .debug_map()
+                .entries(map.iter().map(|(k, v)| (k.val(), v)))
+                .finish(),
+            Val::Set(set) => f.debug_set().entries(set.iter().map(Key::val)).finish(),
+
+            Val::Ref(cell) => write!(f, "Ref({:?})", cell.borrow()),
+            Val::Array(items) => write!(f, "Array({:?})", items.borrow()),
+            Val::Stream(_) => write!(f, "Stream"),
+
+            Val::Op(op) => write!(f, "Op({})", op),
+            Val::Cont(_) => write!(f, "Cont"),
+            Val::CallCC => write!(f, "CallCC"),
+            Val::Escape { machine, .. } => write!(f, "Escape({})", machine),
         }
     }
 }
@@ -37,16 +333,79 @@ impl std::fmt::Debug for Val {
 impl std::fmt::Display for Val {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Val::Abs {
-                val_ctx: _,
-                param: _,
-                body: _,
-            }
-            | Val::Native(_) => write!(f, "<fun>"),
+            Val::Abs { .. } | Val::Native(_) => write!(f, "<fun>"),
+            Val::Thunk(thunk) => match &*thunk.borrow() {
+                Thunk::Delayed(..) => write!(f, "<thunk>"),
+                Thunk::Forced(val) => write!(f, "{}", val),
+            },
 
             Val::Bool(b) => write!(f, "{}", if *b { "True" } else { "False" }),
             Val::Int(i) => write!(f, "{}", *i),
             Val::Unit => write!(f, "Unit"),
+
+            Val::List(list) => {
+                write!(f, "[")?;
+                for (i, val) in list.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            }
+            Val::Pair(pair) => write!(f, "({}, {})", pair.0, pair.1),
+            Val::Con(name, args) => {
+                write!(f, "{}", name)?;
+                for arg in args {
+                    match arg {
+                        Val::Con(_, args) if !args.is_empty() => write!(f, " ({})", arg)?,
+                        Val::Map(_) | Val::Set(_) | Val::Ref(_) | Val::Array(_) => {
+                            write!(f, " ({})", arg)?
+                        }
+                        _ => write!(f, " {}", arg)?,
+                    }
+                }
+                Ok(())
+            }
+
+            Val::Map(map) => {
+                write!(f, "Map {{")?;
+                for (i, (k, v)) in map.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} => {}", k.val(), v)?;
+                }
+                write!(f, "}}")
+            }
+            Val::Set(set) => {
+                write!(f, "Set {{")?;
+                for (i, k) in set.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", k.val())?;
+                }
+                write!(f, "}}")
+            }
+
+            Val::Ref(cell) => write!(f, "ref {}", cell.borrow()),
+            Val::Array(items) => {
+                write!(f, "Array [")?;
+                for (i, val) in items.borrow().iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", val)?;
+                }
+                write!(f, "]")
+            }
+
+            Val::Stream(_) => write!(f, "<stream>"),
+
+            Val::Op(op) => write!(f, "<{}>", op),
+            Val::Cont(_) | Val::Escape { .. } => write!(f, "<continuation>"),
+            Val::CallCC => write!(f, "<fun>"),
         }
     }
 }
@@ -56,6 +415,126 @@ impl Val {
         Val::Native(Rc::new(f))
     }
 
+    pub fn pair(fst: Val, snd: Val) -> Val {
+        Val::Pair(Rc::new((fst, snd)))
+    }
+
+    pub fn con(name: &str, args: Vec<Val>) -> Val {
+        Val::Con(name.to_string(), args)
+    }
+
+    // whether the value can be ordered, i.e. it contains no functions
+    pub fn is_ordered(&self) -> bool {
+        match self {
+            Val::Abs { .. }
+            | Val::Native(_)
+            | Val::Thunk(_)
+            | Val::Op(_)
+            | Val::Cont(_)
+            | Val::CallCC
+            | Val::Escape { .. } => false,
+            Val::Bool(_) | Val::Int(_) | Val::Unit => true,
+            Val::List(list) => list.iter().all(Val::is_ordered),
+            Val::Pair(pair) => pair.0.is_ordered() && pair.1.is_ordered(),
+            Val::Con(_, args) => args.iter().all(Val::is_ordered),
+            // keys were already checked when inserted
+            Val::Map(map) => map.values().all(Val::is_ordered),
+            Val::Set(_) => true,
+            // the contents of a reference may change after it's been ordered
+            Val::Ref(_) | Val::Array(_) => false,
+            // and a stream may contain functions or be infinite
+            Val::Stream(_) => false,
+        }
+    }
+
+    pub fn ref_(val: Val) -> Val {
+        Val::Ref(Rc::new(RefCell::new(val)))
+    }
+
+    pub fn array(items: Vec<Val>) -> Val {
+        Val::Array(Rc::new(RefCell::new(items)))
+    }
+
+    // constructor taking the given number of arguments
+    pub fn constructor(name: &str, arity: usize) -> Val {
+        fn partial(name: String, args: Vec<Val>, arity: usize) -> Val {
+            if args.len() == arity {
+                return Val::Con(name, args);
+            }
+
+            Val::native(move |arg| {
+                let mut args = args.clone();
+                args.push(arg);
+                Ok(partial(name.clone(), args, arity))
+            })
+        }
+
+        partial(name.to_string(), vec![], arity)
+    }
+
+    // second . first, as a closure rather than a native so that the effects
+    // performed by either function can be handled
+    pub fn compose(first: Val, second: Val) -> Val {
+        Val::Abs {
+            val_ctx: ValContext::new()
+                .insert("first".to_string(), first)
+                .insert("second".to_string(), second),
+            param: "x".to_string(),
+            body: Rc::new(Term::app(
+                Term::var("second"),
+                Term::app(Term::var("first"), Term::var("x")),
+            )),
+            strategy: Strategy::ByValue,
+        }
+    }
+
+    // the value of a thunk, evaluating it if it wasn't already
+    pub fn force(&self) -> ValResult<Val> {
+        let thunk = match self {
+            Val::Thunk(thunk) => thunk,
+            val => return Ok(val.clone()),
+        };
+
+        let (term, val_ctx) = match &*thunk.borrow() {
+            Thunk::Forced(val) => return Ok(val.clone()),
+            Thunk::Delayed(term, val_ctx) => (term.clone(), val_ctx.clone()),
+        };
+
+        let val = term.eval_in(&val_ctx, Strategy::ByNeed)?;
+        *thunk.borrow_mut() = Thunk::Forced(val.clone());
+
+        Ok(val)
+    }
+
+    pub fn apply(&self, arg: Val) -> ValResult<Val> {
+        match self {
+            Val::Abs {
+                val_ctx,
+                param,
+                body,
+                strategy,
+            } => {
+                let val_ctx = if param == "_" {
+                    val_ctx.clone()
+                } else {
+                    val_ctx.insert(param.clone(), arg)
+                };
+
+                match body.eval_in(&val_ctx, *strategy) {
+                    Err(ValError::Return(val)) => Ok(val),
+                    res => res,
+                }
+            }
+            Val::Native(f) => f(arg),
+            Val::Op(op) => Err(ValError::Unresumable(op.clone())),
+            Val::Cont(k) => Machine::new(k.resume(), k.strategy).run(Control::Return(arg)),
+            Val::CallCC => Err(ValError::NoContinuation),
+            // unwinds to the machine that captured it
+            Val::Escape { machine, frames } => Err(ValError::Escape(*machine, frames.clone(), Box::new(arg))),
+            _ => unreachable!(),
+        }
+    }
+
     pub fn op1<F: Fn(Val) -> ValResult<Val> + 'static>(f: F) -> Val {
         Val::native(f)
     }
@@ -94,12 +573,112 @@ impl Val {
 
 pub type ValContext = crate::Map<String, Val>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // arguments are evaluated before the call
+    ByValue,
+    // arguments, and the variables of blocks, are evaluated when first used
+    ByNeed,
+}
+
 #[derive(Debug)]
-pub enum ValError {}
+pub enum Thunk {
+    Delayed(Term, ValContext),
+    Forced(Val),
+}
+
+// thunks created under a handler
+type Thunks = Rc<RefCell<Vec<Rc<RefCell<Thunk>>>>>;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Evaluator {
+    // recursive evaluation by Term::eval
+    #[default]
+    Direct,
+    // evaluation by the machine below, keeping the rest of the computation as
+    // an explicit stack so that callcc can capture it
+    Cps,
+    // recursive evaluation by need
+    Lazy,
+}
+
+// longest array arrayMake will allocate
+pub const MAX_ARRAY_LENGTH: i32 = 1 << 24;
+
+#[derive(Debug)]
+pub enum ValError {
+    NoMatch(Val),
+    Unordered(Val),
+    OutOfBounds { index: i32, length: usize },
+    InvalidSlice { start: i32, end: i32, length: usize },
+    NegativeLength(i32),
+    TooLong(i32),
+    Overflow,
+
+    // control flow signals, caught by the enclosing loop or function
+    Break,
+    Continue,
+    Return(Val),
+
+    // exception raised by the program, which unlike the other errors can be
+    // caught
+    Raise(Val),
+
+    // operation performed where no handler can capture its continuation
+    Unresumable(String),
+    // callcc called where its continuation can't be captured
+    NoContinuation,
+    // continuation captured by callcc being applied, which unwinds to the
+    // machine with the given id
+    Escape(usize, Rc<Vec<Frame>>, Box<Val>),
+}
 
 impl std::fmt::Display for ValError {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        todo!()
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValError::NoMatch(val) => write!(f, "No pattern matches the value {}", val),
+            ValError::Unordered(val) => {
+                write!(f, "The value {} can't be ordered, as it contains a function", val)
+            }
+            ValError::OutOfBounds { index, length } => write!(
+                f,
+                "Index {} is out of bounds for an array of length {}",
+                index, length
+            ),
+            ValError::InvalidSlice { start, end, length } => write!(
+                f,
+                "Slice from {} to {} is out of bounds for an array of length {}",
+                start, end, length
+            ),
+            ValError::NegativeLength(length) => {
+                write!(f, "Can't make an array of negative length {}", length)
+            }
+            ValError::TooLong(length) => write!(
+                f,
+                "Can't make an array of length {}, the most is {}",
+                length, MAX_ARRAY_LENGTH
+            ),
+            ValError::Overflow => write!(f, "Integer overflow"),
+
+            ValError::Break => write!(f, "break outside of a loop"),
+            ValError::Continue => write!(f, "continue outside of a loop"),
+            ValError::Return(_) => write!(f, "return outside of a function"),
+
+            ValError::Raise(exn) => write!(f, "Uncaught exception {}", exn),
+
+            ValError::Unresumable(op) => write!(
+                f,
+                "Operation \"{}\" was performed inside a built-in function's callback, where its continuation can't be captured",
+                op
+            ),
+            ValError::NoContinuation => write!(
+                f,
+                "callcc can only capture its continuation when evaluating in continuation-passing style (--cps), outside of built-in functions' callbacks"
+            ),
+            ValError::Escape(..) => {
+                write!(f, "Continuation applied after the evaluation that captured it ended")
+            }
+        }
     }
 }
 
@@ -108,9 +687,15 @@ pub type ValResult<T> = Result<T, ValError>;
 impl Term {
     // Term::eval() should only be called on terms known to pass type-checking
     pub fn eval(&self, val_ctx: &ValContext) -> ValResult<Val> {
+        self.eval_in(val_ctx, Strategy::ByValue)
+    }
+
+    // evaluates the term, passing arguments to functions and binding the
+    // variables of blocks according to the strategy
+    pub fn eval_in(&self, val_ctx: &ValContext, strategy: Strategy) -> ValResult<Val> {
         match self {
             Term::Var { name } => match val_ctx.get(name) {
-                Some(v) => Ok(v.clone()),
+                Some(v) => v.force(),
                 None => unreachable!(),
             },
             Term::Abs {
@@ -120,55 +705,811 @@ impl Term {
             } => Ok(Val::Abs {
                 val_ctx: val_ctx.clone(),
                 param: param_name.clone(),
-                body: *body.clone(),
+                body: Rc::new(*body.clone()),
+                strategy,
             }),
             Term::App { func, arg } => {
-                let func = func.eval(val_ctx)?;
-                let arg = arg.eval(val_ctx)?;
-
-                match &func {
-                    Val::Abs { val_ctx, param, body } => {
-                        let val_ctx = if param == "_" {
-                            val_ctx.clone()
-                        } else {
-                            val_ctx.insert(param.clone(), arg)
-                        };
-
-                        body.eval(&val_ctx)
+                let func = func.eval_in(val_ctx, strategy)?;
+                let arg = match &func {
+                    Val::Abs {
+                        strategy: Strategy::ByNeed,
+                        ..
+                    } => arg.delay(val_ctx)?,
+                    // built-in functions, and those defined by value, get
+                    // their arguments evaluated
+                    _ => arg.eval_in(val_ctx, strategy)?,
+                };
+
+                func.apply(arg)
+            }
+            Term::Compose { first, second } => {
+                let first = first.eval_in(val_ctx, strategy)?;
+                let second = second.eval_in(val_ctx, strategy)?;
+
+                Ok(Val::compose(first, second))
+            }
+
+            Term::Int(i) => Ok(Val::Int(*i)),
+
+            Term::List(items) => Ok(Val::List(
+                items
+                    .iter()
+                    .map(|item| item.eval_in(val_ctx, strategy))
+                    .collect::<ValResult<_>>()?,
+            )),
+            Term::Cons { head, tail } => {
+                let head = head.eval_in(val_ctx, strategy)?;
+                let tail = cast!(tail.eval_in(val_ctx, strategy)?, Val::List);
+
+                Ok(Val::List(List::cons(head, tail)))
+            }
+            Term::Pair { fst, snd } => {
+                let fst = fst.eval_in(val_ctx, strategy)?;
+                let snd = snd.eval_in(val_ctx, strategy)?;
+
+                Ok(Val::pair(fst, snd))
+            }
+
+            Term::Ascribe { term, typ: _ } => term.eval_in(val_ctx, strategy),
+            Term::Hole(_) => unreachable!(),
+
+            Term::Ref(term) => Ok(Val::ref_(term.eval_in(val_ctx, strategy)?)),
+            Term::Deref(term) => {
+                let cell = cast!(term.eval_in(val_ctx, strategy)?, Val::Ref);
+                let val = cell.borrow().clone();
+
+                Ok(val)
+            }
+            Term::Assign { target, val } => {
+                let cell = cast!(target.eval_in(val_ctx, strategy)?, Val::Ref);
+                let val = val.eval_in(val_ctx, strategy)?;
+                *cell.borrow_mut() = val;
+
+                Ok(Val::Unit)
+            }
+
+            Term::If {
+                cond,
+                t_true,
+                t_false,
+            } => match cond.eval_in(val_ctx, strategy)? {
+                Val::Bool(true) => t_true.eval_in(val_ctx, strategy),
+                Val::Bool(false) => t_false.eval_in(val_ctx, strategy),
+                _ => unreachable!(),
+            },
+            Term::Match { scrutinee, arms } => {
+                let val = scrutinee.eval_in(val_ctx, strategy)?;
+
+                for (pat, body) in arms {
+                    if let Some(val_ctx) = pat.bind(&val, val_ctx) {
+                        return body.eval_in(&val_ctx, strategy);
                     }
-                    Val::Native(f) => f(arg),
-                    _ => unreachable!(),
                 }
+
+                Err(ValError::NoMatch(val))
             }
+            Term::Seq(stmts) => eval_block(stmts, val_ctx, strategy),
 
-            Term::Int(i) => Ok(Val::Int(*i)),
+            Term::Raise(exn) => Err(ValError::Raise(exn.eval_in(val_ctx, strategy)?)),
+            Term::Try { body, pat, handler } => match body.eval_in(val_ctx, strategy) {
+                Err(ValError::Raise(exn)) => match pat.bind(&exn, val_ctx) {
+                    Some(val_ctx) => handler.eval_in(&val_ctx, strategy),
+                    None => Err(ValError::Raise(exn)),
+                },
+                res => res,
+            },
+
+            // operations are only performed by handled terms, which are run
+            // by the machine below
+            Term::Perform { op, arg } => {
+                arg.eval_in(val_ctx, strategy)?;
+                Err(ValError::Unresumable(op.clone()))
+            }
+            Term::Handle { .. } => {
+                Machine::new(vec![], strategy).run(Control::Eval(self.clone(), val_ctx.clone()))
+            }
+        }
+    }
+}
+
+impl Term {
+    // a thunk evaluating the term when first forced; variables and constants
+    // are evaluated right away, so that they don't get wrapped twice
+    fn delay(&self, val_ctx: &ValContext) -> ValResult<Val> {
+        match self {
+            Term::Var { name } => match val_ctx.get(name) {
+                Some(v) => Ok(v.clone()),
+                None => unreachable!(),
+            },
+            Term::Int(_) | Term::Abs { .. } => self.eval_in(val_ctx, Strategy::ByNeed),
+            _ => Ok(Val::Thunk(Rc::new(RefCell::new(Thunk::Delayed(
+                self.clone(),
+                val_ctx.clone(),
+            ))))),
+        }
+    }
+
+    // evaluates the term in continuation-passing style, by the machine below
+    pub fn eval_cps(&self, val_ctx: &ValContext) -> ValResult<Val> {
+        Machine::new(vec![], Strategy::ByValue).run(Control::Eval(self.clone(), val_ctx.clone()))
+    }
+}
+
+fn eval_block(stmts: &[Stmt], val_ctx: &ValContext, strategy: Strategy) -> ValResult<Val> {
+    let mut val_ctx = val_ctx.clone();
+
+    let mut val_end = Val::Unit;
+    for stmt in stmts {
+        val_end = stmt.eval(&mut val_ctx, strategy)?;
+    }
+
+    // the last statement may be a let binding a thunk
+    val_end.force()
+}
+
+// runs one iteration of a loop, returning whether to carry on
+fn eval_iteration(body: &[Stmt], val_ctx: &ValContext, strategy: Strategy) -> ValResult<bool> {
+    match eval_block(body, val_ctx, strategy) {
+        Ok(_) | Err(ValError::Continue) => Ok(true),
+        Err(ValError::Break) => Ok(false),
+        Err(why) => Err(why),
+    }
+}
+
+impl Stmt {
+    // evaluates the statement, adding any binding it introduces to val_ctx
+    pub fn eval(&self, val_ctx: &mut ValContext, strategy: Strategy) -> ValResult<Val> {
+        match self {
+            Stmt::Term(term) => term.eval_in(val_ctx, strategy),
+            Stmt::Let(var, term) => {
+                let v = match strategy {
+                    Strategy::ByValue => term.eval_in(val_ctx, strategy)?,
+                    Strategy::ByNeed => term.delay(val_ctx)?,
+                };
+                *val_ctx = val_ctx.insert(var.to_string(), v.clone());
+                Ok(v)
+            }
+
+            Stmt::While { cond, body } => {
+                while cast!(cond.eval_in(val_ctx, strategy)?, Val::Bool) {
+                    if !eval_iteration(body, val_ctx, strategy)? {
+                        break;
+                    }
+                }
+
+                Ok(Val::Unit)
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                let from = cast!(from.eval_in(val_ctx, strategy)?, Val::Int);
+                let to = cast!(to.eval_in(val_ctx, strategy)?, Val::Int);
+
+                for i in from..to {
+                    if !eval_iteration(body, &val_ctx.insert(var.clone(), Val::Int(i)), strategy)? {
+                        break;
+                    }
+                }
+
+                Ok(Val::Unit)
+            }
+            Stmt::Break => Err(ValError::Break),
+            Stmt::Continue => Err(ValError::Continue),
+            Stmt::Return(term) => Err(ValError::Return(term.eval_in(val_ctx, strategy)?)),
+        }
+    }
+}
+
+impl Pat {
+    // matches the value against the pattern, returning the context extended
+    // with the variables it binds on success
+    pub fn bind(&self, val: &Val, val_ctx: &ValContext) -> Option<ValContext> {
+        match (self, val) {
+            (Pat::Wild, _) => Some(val_ctx.clone()),
+            (Pat::Var(name), _) => Some(val_ctx.insert(name.clone(), val.clone())),
+            (Pat::Int(i), Val::Int(j)) if i == j => Some(val_ctx.clone()),
+            (Pat::Con(name, pats), Val::Con(con, vals)) if name == con => pats
+                .iter()
+                .zip(vals)
+                .try_fold(val_ctx.clone(), |val_ctx, (pat, val)| pat.bind(val, &val_ctx)),
+            // constants which aren't built by constructors, e.g. True
+            (Pat::Con(name, pats), _) if pats.is_empty() => match (val_ctx.get(name)?, val) {
+                (Val::Bool(a), Val::Bool(b)) if a == b => Some(val_ctx.clone()),
+                (Val::Unit, Val::Unit) => Some(val_ctx.clone()),
+                _ => None,
+            },
+            (Pat::Nil, Val::List(list)) if list.uncons().is_none() => Some(val_ctx.clone()),
+            (Pat::Cons(head, tail), Val::List(list)) => {
+                let (x, xs) = list.uncons()?;
+                let val_ctx = head.bind(x, val_ctx)?;
+                tail.bind(&Val::List(xs.clone()), &val_ctx)
+            }
+            (Pat::Pair(fst, snd), Val::Pair(pair)) => {
+                let val_ctx = fst.bind(&pair.0, val_ctx)?;
+                snd.bind(&pair.1, &val_ctx)
+            }
+            _ => None,
+        }
+    }
+}
+
+// evaluation with effect handlers
+//
+// handled terms are evaluated by a machine keeping the rest of the
+// computation as an explicit stack of frames, so that performing an
+// operation can capture the frames up to its handler as a continuation.
+// built-in functions still call back into Term::eval, where no operation
+// could be resumed, so the type checker keeps operations out of their
+// callbacks
+
+// what the machine does next
+enum Control {
+    Eval(Term, ValContext),
+    Return(Val),
+    Unwind(ValError),
+}
+
+// what to do with the value of the term being evaluated
+#[derive(Clone, Debug)]
+pub enum Frame {
+    AppArg(Term, ValContext),
+    AppFunc(Val),
+    ComposeSecond(Term, ValContext),
+    ComposeFirst(Val),
+    // items evaluated so far, and the rest of them in reverse
+    List(Vec<Val>, Vec<Term>, ValContext),
+    ConsTail(Term, ValContext),
+    ConsHead(Val),
+    PairSnd(Term, ValContext),
+    PairFst(Val),
+
+    Ref,
+    Deref,
+    AssignVal(Term, ValContext),
+    AssignTarget(Rc<RefCell<Val>>),
+
+    If(Term, Term, ValContext),
+    Match(Vec<(Pat, Term)>, ValContext),
+    // statements of a block from next on, binding the value of the previous
+    // one if it was a let
+    Block {
+        stmts: Rc<[Stmt]>,
+        next: usize,
+        bind: Option<String>,
+        val_ctx: ValContext,
+    },
+    WhileCond(Term, Rc<[Stmt]>, ValContext),
+    WhileBody(Term, Rc<[Stmt]>, ValContext),
+    ForFrom(String, Term, Rc<[Stmt]>, ValContext),
+    ForTo(String, i32, Rc<[Stmt]>, ValContext),
+    ForBody {
+        var: String,
+        i: i32,
+        to: i32,
+        body: Rc<[Stmt]>,
+        val_ctx: ValContext,
+    },
+
+    Return,
+    Raise,
+    Try(Pat, Term, ValContext),
+    // body of a function, where return ends up
+    Call,
+
+    Perform(String),
+    // the thunks created under the handler, which the continuations it
+    // captures reset
+    Handle(Rc<Handler>, ValContext, Thunks),
+
+    // thunk to memoise the value in
+    Force(Rc<RefCell<Thunk>>),
+}
+
+// continuation captured by a handler, which may be resumed any number of
+// times. The thunks created under the handler which weren't forced yet are
+// put back as they were on each resumption, so that one doesn't see the
+// values another forced them to
+pub struct Cont {
+    frames: Vec<Frame>,
+    strategy: Strategy,
+    delayed: Vec<(Rc<RefCell<Thunk>>, Term, ValContext)>,
+}
+
+impl Cont {
+    fn new(frames: Vec<Frame>, strategy: Strategy) -> Self {
+        let mut delayed = vec![];
+        for frame in &frames {
+            if let Frame::Handle(_, _, thunks) = frame {
+                for thunk in thunks.borrow().iter() {
+                    if let Thunk::Delayed(term, val_ctx) = &*thunk.borrow() {
+                        delayed.push((thunk.clone(), term.clone(), val_ctx.clone()));
+                    }
+                }
+            }
+        }
+
+        Cont {
+            frames,
+            strategy,
+            delayed,
+        }
+    }
+
+    // the frames to run the value through when resuming
+    fn resume(&self) -> Vec<Frame> {
+        for (thunk, term, val_ctx) in &self.delayed {
+            *thunk.borrow_mut() = Thunk::Delayed(term.clone(), val_ctx.clone());
+        }
+
+        self.frames.clone()
+    }
+}
+
+// ids of machines, telling which one a continuation captured by callcc
+// belongs to
+static MACHINES: AtomicUsize = AtomicUsize::new(0);
+
+struct Machine {
+    id: usize,
+    stack: Vec<Frame>,
+    strategy: Strategy,
+}
+
+impl Machine {
+    fn new(stack: Vec<Frame>, strategy: Strategy) -> Self {
+        Machine {
+            id: MACHINES.fetch_add(1, atomic::Ordering::Relaxed),
+            stack,
+            strategy,
+        }
+    }
+
+    fn run(mut self, mut control: Control) -> ValResult<Val> {
+        loop {
+            control = match control {
+                Control::Unwind(ValError::Escape(machine, frames, val)) if machine == self.id => {
+                    self.stack = frames.to_vec();
+                    Control::Return(*val)
+                }
+                Control::Eval(term, val_ctx) => self.eval(term, val_ctx),
+                Control::Return(val) => match self.stack.pop() {
+                    Some(frame) => self.resume(frame, val),
+                    None => return Ok(val),
+                },
+                Control::Unwind(why) => match self.stack.pop() {
+                    Some(frame) => self.unwind(frame, why),
+                    None => return Err(why),
+                },
+            }
+        }
+    }
+
+    fn eval(&mut self, term: Term, val_ctx: ValContext) -> Control {
+        match term {
+            Term::Var { name } => match val_ctx.get(&name) {
+                Some(val) => self.force(val.clone()),
+                None => unreachable!(),
+            },
+            Term::Abs { .. } | Term::Int(_) => match term.eval_in(&val_ctx, self.strategy) {
+                Ok(val) => Control::Return(val),
+                Err(why) => Control::Unwind(why),
+            },
+            Term::App { func, arg } => {
+                self.stack.push(Frame::AppArg(*arg, val_ctx.clone()));
+                Control::Eval(*func, val_ctx)
+            }
+            Term::Compose { first, second } => {
+                self.stack.push(Frame::ComposeSecond(*second, val_ctx.clone()));
+                Control::Eval(*first, val_ctx)
+            }
+
+            Term::List(mut items) => {
+                items.reverse();
+                self.list(vec![], items, val_ctx)
+            }
+            Term::Cons { head, tail } => {
+                self.stack.push(Frame::ConsTail(*tail, val_ctx.clone()));
+                Control::Eval(*head, val_ctx)
+            }
+            Term::Pair { fst, snd } => {
+                self.stack.push(Frame::PairSnd(*snd, val_ctx.clone()));
+                Control::Eval(*fst, val_ctx)
+            }
+
+            Term::Ascribe { term, typ: _ } => Control::Eval(*term, val_ctx),
+            Term::Hole(_) => unreachable!(),
+
+            Term::Ref(term) => {
+                self.stack.push(Frame::Ref);
+                Control::Eval(*term, val_ctx)
+            }
+            Term::Deref(term) => {
+                self.stack.push(Frame::Deref);
+                Control::Eval(*term, val_ctx)
+            }
+            Term::Assign { target, val } => {
+                self.stack.push(Frame::AssignVal(*val, val_ctx.clone()));
+                Control::Eval(*target, val_ctx)
+            }
 
             Term::If {
                 cond,
                 t_true,
                 t_false,
-            } => match cond.eval(val_ctx)? {
-                Val::Bool(true) => t_true.eval(val_ctx),
-                Val::Bool(false) => t_false.eval(val_ctx),
+            } => {
+                self.stack.push(Frame::If(*t_true, *t_false, val_ctx.clone()));
+                Control::Eval(*cond, val_ctx)
+            }
+            Term::Match { scrutinee, arms } => {
+                self.stack.push(Frame::Match(arms, val_ctx.clone()));
+                Control::Eval(*scrutinee, val_ctx)
+            }
+            Term::Seq(stmts) => self.block(stmts.into(), 0, val_ctx, Val::Unit),
+
+            Term::Raise(exn) => {
+                self.stack.push(Frame::Raise);
+                Control::Eval(*exn, val_ctx)
+            }
+            Term::Try { body, pat, handler } => {
+                self.stack.push(Frame::Try(pat, *handler, val_ctx.clone()));
+                Control::Eval(*body, val_ctx)
+            }
+
+            Term::Perform { op, arg } => {
+                self.stack.push(Frame::Perform(op));
+                Control::Eval(*arg, val_ctx)
+            }
+            Term::Handle { body, handler } => {
+                self.stack
+                    .push(Frame::Handle(Rc::new(handler), val_ctx.clone(), Thunks::default()));
+                Control::Eval(*body, val_ctx)
+            }
+        }
+    }
+
+    // passes the value to the frame
+    fn resume(&mut self, frame: Frame, val: Val) -> Control {
+        match frame {
+            Frame::AppArg(arg, val_ctx) => match &val {
+                Val::Abs {
+                    strategy: Strategy::ByNeed,
+                    ..
+                } => match self.delay(&arg, &val_ctx) {
+                    Ok(arg) => self.apply(val, arg),
+                    Err(why) => Control::Unwind(why),
+                },
+                _ => {
+                    self.stack.push(Frame::AppFunc(val));
+                    Control::Eval(arg, val_ctx)
+                }
+            },
+            Frame::AppFunc(func) => self.apply(func, val),
+            Frame::Force(thunk) => {
+                *thunk.borrow_mut() = Thunk::Forced(val.clone());
+                Control::Return(val)
+            }
+            Frame::ComposeSecond(second, val_ctx) => {
+                self.stack.push(Frame::ComposeFirst(val));
+                Control::Eval(second, val_ctx)
+            }
+            Frame::ComposeFirst(first) => Control::Return(Val::compose(first, val)),
+
+            Frame::List(mut done, rest, val_ctx) => {
+                done.push(val);
+                self.list(done, rest, val_ctx)
+            }
+            Frame::ConsTail(tail, val_ctx) => {
+                self.stack.push(Frame::ConsHead(val));
+                Control::Eval(tail, val_ctx)
+            }
+            Frame::ConsHead(head) => {
+                Control::Return(Val::List(List::cons(head, cast!(val, Val::List))))
+            }
+            Frame::PairSnd(snd, val_ctx) => {
+                self.stack.push(Frame::PairFst(val));
+                Control::Eval(snd, val_ctx)
+            }
+            Frame::PairFst(fst) => Control::Return(Val::pair(fst, val)),
+
+            Frame::Ref => Control::Return(Val::ref_(val)),
+            Frame::Deref => {
+                let val = cast!(val, Val::Ref).borrow().clone();
+                Control::Return(val)
+            }
+            Frame::AssignVal(term, val_ctx) => {
+                self.stack.push(Frame::AssignTarget(cast!(val, Val::Ref)));
+                Control::Eval(term, val_ctx)
+            }
+            Frame::AssignTarget(cell) => {
+                *cell.borrow_mut() = val;
+                Control::Return(Val::Unit)
+            }
+
+            Frame::If(t_true, t_false, val_ctx) => match val {
+                Val::Bool(true) => Control::Eval(t_true, val_ctx),
+                Val::Bool(false) => Control::Eval(t_false, val_ctx),
                 _ => unreachable!(),
             },
-            Term::Seq(stmts) => {
-                let mut val_ctx = val_ctx.clone();
-
-                let mut val_end = Val::Unit;
-                for stmt in stmts {
-                    val_end = match stmt {
-                        Stmt::Term(term) => term.eval(&val_ctx)?,
-                        Stmt::Let(var, term) => {
-                            let v = term.eval(&val_ctx)?;
-                            val_ctx = val_ctx.insert(var.to_string(), v.clone());
-                            v
-                        }
+            Frame::Match(arms, val_ctx) => {
+                for (pat, body) in arms {
+                    if let Some(val_ctx) = pat.bind(&val, &val_ctx) {
+                        return Control::Eval(body, val_ctx);
+                    }
+                }
+
+                Control::Unwind(ValError::NoMatch(val))
+            }
+            Frame::Block {
+                stmts,
+                next,
+                bind,
+                val_ctx,
+            } => {
+                let val_ctx = match bind {
+                    Some(var) => val_ctx.insert(var, val.clone()),
+                    None => val_ctx,
+                };
+
+                self.block(stmts, next, val_ctx, val)
+            }
+            Frame::WhileCond(cond, body, val_ctx) => {
+                if cast!(val, Val::Bool) {
+                    self.stack
+                        .push(Frame::WhileBody(cond, body.clone(), val_ctx.clone()));
+                    self.block(body, 0, val_ctx, Val::Unit)
+                } else {
+                    Control::Return(Val::Unit)
+                }
+            }
+            Frame::WhileBody(cond, body, val_ctx) => self.while_(cond, body, val_ctx),
+            Frame::ForFrom(var, to, body, val_ctx) => {
+                let from = cast!(val, Val::Int);
+                self.stack
+                    .push(Frame::ForTo(var, from, body, val_ctx.clone()));
+                Control::Eval(to, val_ctx)
+            }
+            Frame::ForTo(var, from, body, val_ctx) => {
+                self.for_(var, from, cast!(val, Val::Int), body, val_ctx)
+            }
+            Frame::ForBody {
+                var,
+                i,
+                to,
+                body,
+                val_ctx,
+            } => self.for_(var, i + 1, to, body, val_ctx),
+
+            Frame::Return => Control::Unwind(ValError::Return(val)),
+            Frame::Raise => Control::Unwind(ValError::Raise(val)),
+            Frame::Try(..) | Frame::Call => Control::Return(val),
+
+            Frame::Perform(op) => self.perform(op, val),
+            Frame::Handle(handler, val_ctx, _) => match &handler.ret {
+                Some((x, term)) => Control::Eval(*term.clone(), val_ctx.insert(x.clone(), val)),
+                None => Control::Return(val),
+            },
+        }
+    }
+
+    // passes the error to the frame, which may catch it
+    fn unwind(&mut self, frame: Frame, why: ValError) -> Control {
+        match (frame, why) {
+            (Frame::WhileBody(..), ValError::Break) => Control::Return(Val::Unit),
+            (Frame::WhileBody(cond, body, val_ctx), ValError::Continue) => {
+                self.while_(cond, body, val_ctx)
+            }
+            (Frame::ForBody { .. }, ValError::Break) => Control::Return(Val::Unit),
+            (
+                Frame::ForBody {
+                    var,
+                    i,
+                    to,
+                    body,
+                    val_ctx,
+                },
+                ValError::Continue,
+            ) => self.for_(var, i + 1, to, body, val_ctx),
+            (Frame::Call, ValError::Return(val)) => Control::Return(val),
+            (Frame::Try(pat, handler, val_ctx), ValError::Raise(exn)) => {
+                match pat.bind(&exn, &val_ctx) {
+                    Some(val_ctx) => Control::Eval(handler, val_ctx),
+                    None => Control::Unwind(ValError::Raise(exn)),
+                }
+            }
+            (_, why) => Control::Unwind(why),
+        }
+    }
+
+    fn apply(&mut self, func: Val, arg: Val) -> Control {
+        match func {
+            Val::Abs {
+                val_ctx, param, body, ..
+            } => {
+                let val_ctx = if param == "_" {
+                    val_ctx
+                } else {
+                    val_ctx.insert(param, arg)
+                };
+
+                self.stack.push(Frame::Call);
+                Control::Eval((*body).clone(), val_ctx)
+            }
+            Val::Native(f) => match f(arg) {
+                Ok(val) => Control::Return(val),
+                Err(why) => Control::Unwind(why),
+            },
+            Val::Op(op) => self.perform(op, arg),
+            Val::Cont(k) => {
+                self.stack.extend(k.resume());
+                Control::Return(arg)
+            }
+            Val::CallCC => {
+                let k = Val::Escape {
+                    machine: self.id,
+                    frames: Rc::new(self.stack.clone()),
+                };
+
+                self.apply(arg, k)
+            }
+            Val::Escape { machine, frames } => {
+                Control::Unwind(ValError::Escape(machine, frames, Box::new(arg)))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // runs the clause of the innermost handler of the operation, with the
+    // frames up to and including the handler as its continuation
+    fn perform(&mut self, op: String, arg: Val) -> Control {
+        let found = self.stack.iter().enumerate().rev().find_map(|(i, frame)| match frame {
+            Frame::Handle(handler, val_ctx, _) => handler
+                .ops
+                .iter()
+                .find(|clause| clause.op == op)
+                .map(|clause| (i, clause.clone(), val_ctx.clone())),
+            _ => None,
+        });
+
+        match found {
+            Some((i, clause, val_ctx)) => {
+                let k = Val::Cont(Rc::new(Cont::new(self.stack.split_off(i), self.strategy)));
+                let val_ctx = val_ctx.insert(clause.param, arg).insert(clause.k, k);
+
+                Control::Eval(clause.body, val_ctx)
+            }
+            None => Control::Unwind(ValError::Unresumable(op)),
+        }
+    }
+
+    // the value of a thunk, forced by the machine so that the operations it
+    // performs can be handled
+    fn force(&mut self, val: Val) -> Control {
+        let thunk = match val {
+            Val::Thunk(thunk) => thunk,
+            val => return Control::Return(val),
+        };
+
+        let (term, val_ctx) = match &*thunk.borrow() {
+            Thunk::Forced(val) => return Control::Return(val.clone()),
+            Thunk::Delayed(term, val_ctx) => (term.clone(), val_ctx.clone()),
+        };
+
+        self.stack.push(Frame::Force(thunk));
+        Control::Eval(term, val_ctx)
+    }
+
+    // delays the term, remembering the thunk in the innermost handler
+    fn delay(&mut self, term: &Term, val_ctx: &ValContext) -> ValResult<Val> {
+        let val = term.delay(val_ctx)?;
+
+        if let Val::Thunk(thunk) = &val {
+            let handler = self.stack.iter().rev().find_map(|frame| match frame {
+                Frame::Handle(_, _, thunks) => Some(thunks),
+                _ => None,
+            });
+            if let Some(thunks) = handler {
+                thunks.borrow_mut().push(thunk.clone());
+            }
+        }
+
+        Ok(val)
+    }
+
+    fn list(&mut self, done: Vec<Val>, mut rest: Vec<Term>, val_ctx: ValContext) -> Control {
+        match rest.pop() {
+            Some(item) => {
+                self.stack.push(Frame::List(done, rest, val_ctx.clone()));
+                Control::Eval(item, val_ctx)
+            }
+            None => Control::Return(Val::List(done.into_iter().collect())),
+        }
+    }
+
+    // runs the statements of a block from next on, last being the value of
+    // the previous one
+    fn block(&mut self, stmts: Rc<[Stmt]>, next: usize, val_ctx: ValContext, last: Val) -> Control {
+        let stmt = match stmts.get(next) {
+            Some(stmt) => stmt.clone(),
+            // the last statement may be a let binding a thunk
+            None => return self.force(last),
+        };
+
+        let mut frame = Frame::Block {
+            stmts,
+            next: next + 1,
+            bind: None,
+            val_ctx: val_ctx.clone(),
+        };
+
+        match stmt {
+            Stmt::Term(term) => {
+                self.stack.push(frame);
+                Control::Eval(term, val_ctx)
+            }
+            Stmt::Let(var, term) if self.strategy == Strategy::ByNeed => match self.delay(&term, &val_ctx) {
+                Ok(val) => {
+                    let stmts = match frame {
+                        Frame::Block { stmts, .. } => stmts,
+                        _ => unreachable!(),
                     };
+
+                    self.block(stmts, next + 1, val_ctx.insert(var, val.clone()), val)
                 }
+                Err(why) => Control::Unwind(why),
+            },
+            Stmt::Let(var, term) => {
+                if let Frame::Block { bind, .. } = &mut frame {
+                    *bind = Some(var);
+                }
+
+                self.stack.push(frame);
+                Control::Eval(term, val_ctx)
+            }
 
-                Ok(val_end)
+            Stmt::While { cond, body } => {
+                self.stack.push(frame);
+                self.while_(cond, body.into(), val_ctx)
+            }
+            Stmt::For {
+                var,
+                from,
+                to,
+                body,
+            } => {
+                self.stack.push(frame);
+                self.stack
+                    .push(Frame::ForFrom(var, to, body.into(), val_ctx.clone()));
+                Control::Eval(from, val_ctx)
+            }
+            Stmt::Break => Control::Unwind(ValError::Break),
+            Stmt::Continue => Control::Unwind(ValError::Continue),
+            Stmt::Return(term) => {
+                self.stack.push(Frame::Return);
+                Control::Eval(term, val_ctx)
             }
         }
     }
+
+    fn while_(&mut self, cond: Term, body: Rc<[Stmt]>, val_ctx: ValContext) -> Control {
+        self.stack.push(Frame::WhileCond(cond.clone(), body, val_ctx.clone()));
+        Control::Eval(cond, val_ctx)
+    }
+
+    fn for_(&mut self, var: String, i: i32, to: i32, body: Rc<[Stmt]>, val_ctx: ValContext) -> Control {
+        if i >= to {
+            return Control::Return(Val::Unit);
+        }
+
+        self.stack.push(Frame::ForBody {
+            var: var.clone(),
+            i,
+            to,
+            body: body.clone(),
+            val_ctx: val_ctx.clone(),
+        });
+        self.block(body, 0, val_ctx.insert(var, Val::Int(i)), Val::Unit)
+    }
 }
@@ -0,0 +1,128 @@
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use fun::{
+    ast::Decl,
+    grammar::ProgramParser,
+    lexer::Lexer,
+    terms::Term,
+    typs::{Typ, TypError},
+    vals::{Evaluator, Val, ValResult},
+    ProgramContext,
+};
+
+// runs the program with the evaluator, returning the lines it printed, or
+// its type errors or the first error evaluating it
+pub fn run(src: &str, evaluator: Evaluator) -> Result<Vec<String>, String> {
+    run_with(src, evaluator, |ctx, term| ctx.eval(term))
+}
+
+// runs the program, evaluating each definition and main with eval
+pub fn run_with<F>(src: &str, evaluator: Evaluator, eval: F) -> Result<Vec<String>, String>
+where
+    F: Fn(&ProgramContext, &Term) -> ValResult<Val>,
+{
+    let program = ProgramParser::new()
+        .parse(Lexer::new(src))
+        .map_err(|why| format!("Parse error: {}", why))?;
+
+    let out = Rc::new(RefCell::new(vec![]));
+    let printed = out.clone();
+    let mut ctx = ProgramContext::default().with_evaluator(evaluator).insert_val(
+        "print",
+        &Typ::forall(&["a"], Typ::func_eff(Typ::var("a"), Typ::atom("Unit"), Typ::io())),
+        &Val::native(move |n| {
+            printed.borrow_mut().push(n.to_string());
+            Ok(Val::Unit)
+        }),
+    );
+
+    let names: Vec<&str> = program
+        .iter()
+        .flat_map(|item| match &item.decl {
+            Decl::Let(name, _) | Decl::Exception(name, _) => vec![name.as_str()],
+            Decl::Effect(_, ops) => ops.iter().map(|(op, _)| op.as_str()).collect(),
+            _ => vec![],
+        })
+        .collect();
+
+    // as in main.rs, type errors are collected from every definition, but
+    // nothing is evaluated after the first
+    let mut errors = vec![];
+    for item in &program {
+        match &item.decl {
+            Decl::Type(_, _) => unimplemented!(),
+            Decl::Exception(name, args) => match ctx.insert_exception(name, args) {
+                Ok(new) => ctx = new,
+                Err(why) => {
+                    report(&mut errors, name, &why);
+                    ctx = ctx.insert_typ(name, &Typ::Unknown);
+                }
+            },
+            Decl::Effect(name, ops) => match ctx.insert_effect(name, ops) {
+                Ok(new) => ctx = new,
+                Err(why) => {
+                    report(&mut errors, name, &why);
+                    for (op, _) in ops {
+                        ctx = ctx.insert_typ(op, &Typ::Unknown);
+                    }
+                }
+            },
+            Decl::Let(name, term) if name == "main" => {
+                let main_typ = Typ::func_eff(Typ::atom("Unit"), Typ::atom("Unit"), Typ::io());
+                match ctx.check(term, &main_typ) {
+                    Err(why) => report(&mut errors, name, &why),
+                    Ok(()) if errors.is_empty() => {
+                        eval(&ctx, &Term::app(term.clone(), Term::var("Unit")))
+                            .map_err(|why| format!("Eval error: {}", why))?;
+                    }
+                    Ok(()) => {}
+                }
+            }
+            Decl::Let(name, term) => match ctx.typ(term) {
+                Err(why) => {
+                    let later = &names[names.iter().position(|n| n == name).unwrap() + 1..];
+                    report(&mut errors, name, &why.declared_later(later));
+                    ctx = ctx.insert_typ(name, &Typ::Unknown);
+                }
+                Ok(typ) if !errors.is_empty() => ctx = ctx.insert_typ(name, &typ),
+                Ok(typ) => {
+                    let val = eval(&ctx, term).map_err(|why| format!("Eval error in {}: {}", name, why))?;
+                    ctx = ctx.insert_val(name, &typ, &val);
+                }
+            },
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+
+    let out = out.borrow().clone();
+    Ok(out)
+}
+
+fn report(errors: &mut Vec<String>, name: &str, why: &TypError) {
+    match why {
+        TypError::Many(whys) => whys.iter().for_each(|why| report(errors, name, why)),
+        _ => errors.push(format!("Type error in {}: {}", name, why)),
+    }
+}
+
+// the type errors the program is rejected with, one per line
+pub fn typ_error(src: &str) -> String {
+    match run(src, Evaluator::Direct) {
+        Err(why) if why.starts_with("Type error") => why,
+        res => panic!("expected a type error, got {:?}", res),
+    }
+}
+
+// the error evaluating the program
+pub fn eval_error(src: &str, evaluator: Evaluator) -> String {
+    match run(src, evaluator) {
+        Err(why) if why.starts_with("Eval error") => why,
+        res => panic!("expected an eval error, got {:?}", res),
+    }
+}
@@ -0,0 +1,185 @@
+mod common;
+
+use common::{eval_error, run};
+use fun::{grammar::TermParser, lexer::Lexer, vals::Evaluator, ProgramContext};
+
+#[test]
+fn array_make_length() {
+    let out = run(
+        "let main = fun _: Unit => print (arrayLength (arrayMake 3 0));",
+        Evaluator::Direct,
+    );
+    assert_eq!(out, Ok(vec!["3".to_string()]));
+
+    let out = run("let a = arrayMake 2147483647 0;", Evaluator::Direct);
+    assert!(
+        matches!(&out, Err(why) if why.contains("Can't make an array of length 2147483647")),
+        "{:?}",
+        out
+    );
+}
+
+#[test]
+fn maps_and_sets_normalize_to_terms() {
+    let ctx = ProgramContext::default();
+    for src in ["mapFromList [(1, [2]), (3, [])]", "setFromList [True]", "(mapEmpty : Map Int Int)"] {
+        let term = TermParser::new().parse(Lexer::new(src)).unwrap();
+        let (_, nf) = ctx.normalize(&term).unwrap();
+        let (val, again) = (ctx.eval(&term).unwrap(), ctx.eval(&nf).unwrap());
+        assert_eq!(val.to_string(), again.to_string(), "{}", nf);
+    }
+}
+
+#[test]
+fn list_combinators() {
+    let out = run(
+        "let xs = [1, 2, 3];
+         let main = fun _: Unit => {
+           print (map (fun x => mul x x) xs);
+           print (filter (fun x => gt x 1) (range 0 5));
+           print (foldl (fun acc x => x :: acc) [] xs, foldr (fun x acc => x :: acc) [0] xs);
+           print (length [], (reverse xs, append xs [4]));
+           print (zip xs [True, False], sum (range 1 101))
+         };",
+        Evaluator::Direct,
+    );
+    let expected = [
+        "[1, 4, 9]",
+        "[2, 3, 4]",
+        "([3, 2, 1], [1, 2, 3, 0])",
+        "(0, ([3, 2, 1], [1, 2, 3, 4]))",
+        "([(1, True), (2, False)], 5050)",
+    ];
+    assert_eq!(out, Ok(expected.map(String::from).to_vec()));
+}
+
+#[test]
+fn sum_overflow() {
+    let why = eval_error("let s = sum [2147483647, 1];", Evaluator::Direct);
+    assert!(why.contains("Integer overflow"), "{}", why);
+}
+
+#[test]
+fn option_and_result_combinators() {
+    let out = run(
+        "let half (x: Int) : Option Int = if eq (mod x 2) 0 then Some (div x 2) else None;
+         let describe (r: Result Bool Int) : Int = match r with { Ok n => n, Err b => if b then 1 else 0 };
+         let main = fun _: Unit => {
+           print (checkedDiv 7 2, checkedDiv 7 0);
+           print (optionMap (fun x => add x 1) (Some 1), optionAndThen half (Some 3));
+           print (optionUnwrapOr 0 (half 8), optionUnwrapOr 0 None);
+           print (okOr False (half 5), resultMap (fun x => mul x 10) (okOr True (Some 4)));
+           print (resultAndThen (fun x => okOr False (half x)) (Ok 6), resultUnwrapOr 9 (Err True));
+           print (describe (Ok 5), describe (Err True))
+         };",
+        Evaluator::Direct,
+    );
+    let expected = [
+        "(Some 3, None)",
+        "(Some 2, None)",
+        "(4, 0)",
+        "(Err False, Ok 40)",
+        "(Ok 3, 9)",
+        "(5, 1)",
+    ];
+    assert_eq!(out, Ok(expected.map(String::from).to_vec()));
+}
+
+#[test]
+fn match_without_an_arm() {
+    let why = eval_error(
+        "let x = match checkedDiv 1 0 with { Some n => n };",
+        Evaluator::Direct,
+    );
+    assert!(why.contains("No pattern matches the value None"), "{}", why);
+}
+
+#[test]
+fn references() {
+    let out = run(
+        "let counter = ref 0;
+         let bump (n: Int) : Unit = counter := add !counter n;
+         let main = fun _: Unit => {
+           bump 2;
+           bump 3;
+           let alias = counter;
+           alias := mul !alias 10;
+           print !counter;
+           let xs = ref [1];
+           xs := 2 :: !xs;
+           print !xs
+         };",
+        Evaluator::Direct,
+    );
+    assert_eq!(out, Ok(vec!["50".to_string(), "[2, 1]".to_string()]));
+}
+
+#[test]
+fn loops_with_break_and_continue() {
+    let src = "let main = fun _: Unit => {
+                 let total = ref 0;
+                 for i in 0..10 do {
+                   if eq (mod i 2) 0 then { continue } else Unit;
+                   if gt i 6 then { break } else Unit;
+                   total := add !total i
+                 };
+                 print !total;
+                 let n = ref 0;
+                 while True do {
+                   n := add !n 1;
+                   if eq !n 4 then { break } else Unit
+                 };
+                 print !n;
+                 for i in 3..3 do { print i }
+               };";
+    for evaluator in [Evaluator::Direct, Evaluator::Cps, Evaluator::Lazy] {
+        assert_eq!(run(src, evaluator), Ok(vec!["9".to_string(), "4".to_string()]));
+    }
+}
+
+#[test]
+fn early_return() {
+    let src = "let find (xs: List Int) : Int = {
+                 let rest = ref xs;
+                 while True do {
+                   match !rest with {
+                     [] => { return 0 },
+                     x :: tail => { if gt x 2 then { return x } else Unit; rest := tail }
+                   }
+                 };
+                 0
+               };
+               let main = fun _: Unit => print (find [1, 2, 5, 3], find [1]);";
+    for evaluator in [Evaluator::Direct, Evaluator::Cps, Evaluator::Lazy] {
+        assert_eq!(run(src, evaluator), Ok(vec!["(5, 0)".to_string()]));
+    }
+}
+
+#[test]
+fn streams_are_lazy() {
+    // fails once forced, as no arm matches
+    let defs = "let fail (x: Int) : Int = match None with { Some y => y };
+                let nats = iterate (fun x => add x 1) 0;
+                let fragile = streamMap (fun x => if eq x 3 then fail x else x) nats;";
+
+    let src = format!(
+        "{}
+         let main = fun _: Unit => {{
+           print (toList (take 3 fragile));
+           print (toList (take 2 (drop 5 (iterate (fun x => mul x 2) 1))));
+           print (toList (unfold (fun n => if gt n 3 then None else Some (n, add n 1)) 0));
+           match streamUncons (streamCons 1 (fun _ => drop (fail 0) nats)) with {{
+             Some (x, _) => print x,
+             None => Unit
+           }}
+         }};",
+        defs
+    );
+    let expected = ["[0, 1, 2]", "[32, 64]", "[0, 1, 2, 3]", "1"].map(String::from).to_vec();
+    for evaluator in [Evaluator::Direct, Evaluator::Cps, Evaluator::Lazy] {
+        assert_eq!(run(&src, evaluator), Ok(expected.clone()));
+    }
+
+    let why = eval_error(&format!("{} let xs = toList (take 4 fragile);", defs), Evaluator::Direct);
+    assert!(why.contains("Eval error in xs: No pattern matches"), "{}", why);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{run, run_with};
+use fun::{steps::Order, terms::Term, typs::TypError, vals::Evaluator};
+
+const PROGRAMS: &[&str] = &[
+    "basics",
+    "lists",
+    "patterns",
+    "exceptions",
+    "effects",
+    "choice",
+    "maps",
+];
+
+fn source(name: &str) -> String {
+    let path = format!("{}/tests/programs/{}.fun", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(path).unwrap()
+}
+
+// what the program prints under Term::eval
+fn expected(src: &str) -> Vec<String> {
+    run(src, Evaluator::Direct).unwrap()
+}
+
+#[test]
+fn cps_agrees_with_eval() {
+    for name in PROGRAMS {
+        let src = source(name);
+        assert_eq!(run(&src, Evaluator::Cps), Ok(expected(&src)), "{}", name);
+    }
+}
+
+#[test]
+fn lazy_agrees_with_eval() {
+    for name in PROGRAMS {
+        let src = source(name);
+        assert_eq!(run(&src, Evaluator::Lazy), Ok(expected(&src)), "{}", name);
+    }
+}
+
+#[test]
+fn steps_agree_with_eval() {
+    for order in [Order::ByValue, Order::ByName, Order::Normal] {
+        for name in PROGRAMS {
+            let src = source(name);
+            let out = run_with(&src, Evaluator::Direct, |ctx, term| ctx.trace(term, order, |_| ()));
+            assert_eq!(out, Ok(expected(&src)), "{} under {:?}", name, order);
+        }
+    }
+}
+
+// each definition is replaced by its normal form, evaluated again
+#[test]
+fn normal_forms_agree_with_eval() {
+    for name in PROGRAMS {
+        let src = source(name);
+        let out = run_with(&src, Evaluator::Direct, |ctx, term| {
+            let val = ctx.eval(term)?;
+            match ctx.typ(term) {
+                Ok(typ) => ctx.eval(&ctx.reify(&val, &typ)),
+                // the application of main does IO, which isn't allowed here
+                Err(why) if matches!(term, Term::App { .. }) && only_io(&why) => Ok(val),
+                Err(why) => panic!("{}: {}", name, why),
+            }
+        });
+        assert_eq!(out, Ok(expected(&src)), "{}", name);
+    }
+}
+
+fn only_io(why: &TypError) -> bool {
+    match why {
+        TypError::Unhandled(effect) => effect == "IO",
+        TypError::Many(errs) => errs.iter().all(only_io),
+        _ => false,
+    }
+}
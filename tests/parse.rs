@@ -0,0 +1,47 @@
+use fun::{ast::Decl, grammar::ProgramParser, lexer::Lexer, ProgramContext};
+
+#[test]
+fn doc_comments_attach_to_decls() {
+    let src = "/// the answer\nlet x = 42;\nlet y = 1;";
+    let program = ProgramParser::new().parse(Lexer::new(src)).unwrap();
+
+    assert_eq!(program[0].doc.as_deref(), Some("the answer"));
+    assert_eq!(program[1].doc, None);
+}
+
+#[test]
+fn doc_comments_inside_blocks() {
+    let src = "let f = fun (x: Int) => {\n  /// not a decl\n  let y = x;\n  y\n};";
+    assert!(ProgramParser::new().parse(Lexer::new(src)).is_ok());
+}
+
+#[test]
+fn doc_comment_at_end_of_file() {
+    let src = "let x = 1;\n/// trailing\n";
+    assert_eq!(ProgramParser::new().parse(Lexer::new(src)).unwrap().len(), 1);
+}
+
+#[test]
+fn doc_comments_are_described() {
+    let src = "/// raised on []\nexception Empty;\n/// doubles\n/// its argument\nlet double = fun x: Int => mul x 2;\nlet one = 1;";
+    let program = ProgramParser::new().parse(Lexer::new(src)).unwrap();
+
+    let mut ctx = ProgramContext::default();
+    for item in &program {
+        ctx = match &item.decl {
+            Decl::Exception(name, args) => ctx.insert_exception(name, args).unwrap(),
+            Decl::Let(name, term) => ctx.insert_typ(name, &ctx.typ(term).unwrap()),
+            _ => unreachable!(),
+        };
+    }
+
+    let docs: Vec<_> = program.iter().filter_map(|item| ctx.describe(item)).collect();
+    assert_eq!(
+        docs,
+        vec![
+            "/// raised on []\nEmpty : Exn",
+            "/// doubles\n/// its argument\ndouble : (Int -> Int)",
+            "one : Int",
+        ]
+    );
+}
@@ -0,0 +1,278 @@
+mod common;
+
+use common::{run, typ_error};
+use fun::vals::Evaluator;
+
+#[test]
+fn map_keys_must_be_ordered() {
+    let why = typ_error("let m = mapInsert (fun (x: Int) => x) 1 mapEmpty;");
+    assert!(why.contains("can't be ordered"), "{}", why);
+
+    let why = typ_error("let s = setInsert not setEmpty;");
+    assert!(why.contains("can't be ordered"), "{}", why);
+
+    // through a polymorphic function taking a map
+    let why = typ_error(
+        "let ins (m: Map a Int) (k: a) : Map a Int = mapInsert k 1 m;
+         let bad = ins mapEmpty not;",
+    );
+    assert!(why.contains("can't be ordered"), "{}", why);
+
+    let why = typ_error("let bad (s: Set (Int -> Int)) = setSize s;");
+    assert!(why.contains("can't be ordered"), "{}", why);
+}
+
+#[test]
+fn ordered_map_keys() {
+    let out = run(
+        "let m = mapInsert [1, 2] (1, True) (mapInsert [3] (2, False) mapEmpty);
+         let main = fun _: Unit => print (mapSize m);",
+        Evaluator::Direct,
+    );
+    assert_eq!(out, Ok(vec!["2".to_string()]));
+}
+
+#[test]
+fn exception_payload_is_monomorphic() {
+    let why = typ_error(
+        "exception Foo a;
+         let f (x: a) : Unit = raise (Foo x);
+         let g (y: a) : a = try { f 1; y } catch Foo z => z;
+         let main = fun _: Unit => print (and (g True) True);",
+    );
+    assert!(why.contains("Type error in Foo") && why.contains("Type variable a"), "{}", why);
+
+    let out = run(
+        "exception Foo Int;
+         let g (y: Int) : Int = try { raise (Foo y) } catch Foo z => add z 1;
+         let main = fun _: Unit => print (g 1);",
+        Evaluator::Direct,
+    );
+    assert_eq!(out, Ok(vec!["2".to_string()]));
+}
+
+#[test]
+fn effect_operations_are_monomorphic_arrows() {
+    let why = typ_error("effect Choose { choose: a -> a };");
+    assert!(why.contains("Type error in Choose") && why.contains("Type variable a"), "{}", why);
+
+    let why = typ_error("effect State { get: Int };");
+    assert!(why.contains("must have an arrow type"), "{}", why);
+
+    let out = run(
+        "effect State { get: Unit -> Int };
+         let main = fun _: Unit => print (handle add (perform get Unit) 1 with { get _ k => k 41 });",
+        Evaluator::Direct,
+    );
+    assert_eq!(out, Ok(vec!["42".to_string()]));
+}
+
+#[test]
+fn callcc_only_under_cps() {
+    let src = "let main = fun _: Unit => print (add 1 (callcc (fun (k: Int -> Int) => mul 10 (k 5))));";
+    assert_eq!(run(src, Evaluator::Cps), Ok(vec!["6".to_string()]));
+
+    let why = typ_error(src);
+    assert!(why.contains("\"callcc\" isn't defined"), "{}", why);
+    let out = run(src, Evaluator::Lazy);
+    assert!(matches!(&out, Err(why) if why.contains("\"callcc\" isn't defined")), "{:?}", out);
+}
+
+#[test]
+fn anonymous_hole() {
+    let why = typ_error("let f (x: Int) : Int = add ? 1;");
+    assert!(why.contains("Found hole ? : Int") && why.contains("x : Int"), "{}", why);
+}
+
+#[test]
+fn holes_report_type_and_bindings() {
+    let why = typ_error(
+        "let twice (f: Int -> Int) (x: Int) : Int = f (f x);
+         let inc (x: Int) : Int = add x 1;
+         let main = fun _: Unit => {
+           let z = 3;
+           print (twice ?fn z);
+           print (add ?n 1)
+         };",
+    );
+    let lines: Vec<_> = why.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Type error in main: Found hole ?fn : (Int -> Int)",
+            "    z : Int",
+            "  in scope with this type: inc",
+            "Type error in main: Found hole ?n : Int",
+            "    z : Int",
+        ]
+    );
+}
+
+#[test]
+fn hole_without_expected_type() {
+    let why = typ_error("let f (x: Bool) = ?what;");
+    assert!(why.starts_with("Type error in f: Found hole ?what : "), "{}", why);
+    assert!(why.contains("x : Bool"), "{}", why);
+}
+
+#[test]
+fn errors_accumulate_across_definitions() {
+    let why = typ_error(
+        "let f (x: Int) : Bool = add x True;
+         let g (y: Int) : Int = add (not y) (f y);
+         let h = f 1;
+         let main = fun _: Unit => print (h, g 2);",
+    );
+    let lines: Vec<_> = why.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Type error in f: Expected type \"Int\" but found \"Bool\"",
+            "Type error in f: Expected type \"Bool\" but found \"Int\"",
+            // f failing doesn't make every use of it fail too
+            "Type error in g: Expected type \"Bool\" but found \"Int\"",
+            "Type error in g: Expected type \"Int\" but found \"Bool\"",
+        ]
+    );
+}
+
+#[test]
+fn well_typed_definitions_add_no_errors() {
+    let why = typ_error(
+        "let f (x: Int) : Int = add x True;
+         let main = fun _: Unit => print 1;",
+    );
+    assert_eq!(why, "Type error in f: Expected type \"Int\" but found \"Bool\"");
+}
+
+#[test]
+fn undefined_variables_suggest_similar_names() {
+    let why = typ_error(
+        "let f (x: Int) : Int = ad x (helper x);
+         let g (x: Int) : Bool = gt x 0 |> nott;
+         let h (x: Int) : Int = helpr x;
+         let helper (x: Int) : Int = mul x zz;
+         let k (count: Int) : Int = add cout 1;",
+    );
+    let lines: Vec<_> = why.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "Type error in f: Variable \"ad\" isn't defined, did you mean \"add\" or \"and\"?",
+            "Type error in f: Variable \"helper\" isn't defined yet, it's only declared further down",
+            "Type error in g: Variable \"nott\" isn't defined, did you mean \"not\"?",
+            "Type error in h: Variable \"helpr\" isn't defined, did you mean \"helper\" (declared further down)?",
+            "Type error in helper: Variable \"zz\" isn't defined",
+            // locals are suggested as well as globals
+            "Type error in k: Variable \"cout\" isn't defined, did you mean \"count\"?",
+        ]
+    );
+}
+
+#[test]
+fn undefined_variable_reported_once() {
+    let why = typ_error("let f (x: Int) : Int = add (foo x) (foo 1);");
+    assert_eq!(why.lines().count(), 1, "{}", why);
+}
+
+#[test]
+fn lists_are_homogeneous() {
+    let why = typ_error("let xs = [1, True];");
+    assert!(why.contains("Expected type \"Int\" but found \"Bool\""), "{}", why);
+
+    let why = typ_error("let ys = map not [1];");
+    assert!(why.contains("Expected type \"List Bool\" but found \"List Int\""), "{}", why);
+}
+
+#[test]
+fn constructor_patterns_are_checked() {
+    let why = typ_error("let f (x: Option Int) : Int = match x with { Some a b => a, None => 0 };");
+    assert!(why.contains("Constructor \"Some\" takes 1 argument(s) but the pattern gives it 2"), "{}", why);
+
+    let why = typ_error("let g (x: Result Bool Int) : Int = match x with { Ok b => if b then 1 else 0, Err _ => 0 };");
+    assert!(why.contains("Expected type"), "{}", why);
+}
+
+#[test]
+fn references_obey_the_value_restriction() {
+    let why = typ_error("let bad = ref [];");
+    assert!(why.contains("Can't fully infer the type \"Ref (List"), "{}", why);
+
+    // a reference made by a function call is still not a value
+    let why = typ_error("let cell (x: a) : Ref a = ref x; let bad = cell [];");
+    assert!(why.contains("Can't fully infer the type \"Ref (List"), "{}", why);
+
+    let why = typ_error("let id (x: a) : a = x; let bad = ref id;");
+    assert!(why.contains("Can't fully infer the type \"Ref (_ -> _)\""), "{}", why);
+
+    let why = typ_error("let r = ref 1; let bad = r := True;");
+    assert!(why.contains("Expected type \"Int\" but found \"Bool\""), "{}", why);
+
+    let why = typ_error("let bad = !1;");
+    assert!(why.contains("Ref"), "{}", why);
+}
+
+#[test]
+fn break_and_continue_outside_a_loop() {
+    let why = typ_error("let a = { break };");
+    assert!(why.contains("\"break\" can only be used inside a loop"), "{}", why);
+
+    // a function body starts outside of any loop around it
+    let why = typ_error("let b = { while True do { let f = fun _: Unit => { continue }; Unit }; Unit };");
+    assert!(why.contains("\"continue\" can only be used inside a loop"), "{}", why);
+}
+
+#[test]
+fn return_is_checked_against_the_function() {
+    let why = typ_error("let b = { return 1 };");
+    assert!(why.contains("\"return\" can only be used inside a function"), "{}", why);
+
+    let why = typ_error("let c (x: Int) : Int = { return True };");
+    assert!(why.contains("Expected type \"Int\" but found \"Bool\""), "{}", why);
+}
+
+#[test]
+fn io_is_kept_out_of_pure_definitions() {
+    let why = typ_error("let f : Int -> Int = fun (x: Int) => { print x; x };");
+    assert!(why.contains("Effect \"IO\" is neither handled here"), "{}", why);
+
+    // through a function doing IO, and a built-in's callback
+    let src = "let loud (x: Int) : Int = { print x; x };
+               let g : Int -> Int = fun (x: Int) => loud x;
+               let h : List Int -> List Int = fun (xs: List Int) => map loud xs;
+               let k : List Int ->{IO} List Int = fun (xs: List Int) => map loud xs;";
+    let why = typ_error(src);
+    assert!(why.contains("Type error in g: Effect \"IO\""), "{}", why);
+    assert!(why.contains("Type error in h: Effect \"IO\""), "{}", why);
+    assert_eq!(why.lines().count(), 2, "{}", why);
+}
+
+#[test]
+fn builtin_callbacks_perform_no_operations() {
+    let src = "effect Gen { yield : Int -> Unit };
+               let bad = handle { map (fun x => perform yield x) [1, 2]; 0 } with {
+                 yield x k => add x (k Unit),
+               };";
+    let why = typ_error(src);
+    assert!(why.contains("Effect \"Gen\" can't be performed inside a built-in function's callback"), "{}", why);
+
+    // the callback of a row-polymorphic function might perform anything
+    let why = typ_error("let apply (f: Int ->{e} Int) (xs: List Int) = map f xs;");
+    assert!(why.contains("Effect \"e\" can't be performed"), "{}", why);
+}
+
+#[test]
+fn builtin_callbacks_may_do_io() {
+    let src = "effect Gen { yield : Int -> Unit };
+               let total = handle { let ys = map (fun x => add x 1) [1, 2]; perform yield (sum ys); 0 } with {
+                 yield x k => add x (k Unit),
+               };
+               let main = fun _: Unit => {
+                 map print [1, 2];
+                 print (foldl (fun acc x => { print x; add acc x }) total [3])
+               };";
+    let expected = ["1", "2", "3", "8"].map(String::from).to_vec();
+    for evaluator in [Evaluator::Direct, Evaluator::Cps, Evaluator::Lazy] {
+        assert_eq!(run(src, evaluator), Ok(expected.clone()));
+    }
+}